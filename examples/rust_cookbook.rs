@@ -31,7 +31,7 @@ fn main() -> CmdResult {
     spawn_with_output!(journalctl)?.wait_with_pipe(&mut |pipe| {
         BufReader::new(pipe)
             .lines()
-            .filter_map(|line| line.ok())
+            .map_while(Result::ok)
             .filter(|line| line.find("usb").is_some())
             .take(10)
             .for_each(|line| println!("{}", line));
@@ -1,34 +1,93 @@
 use crate::parser::{ParseArg, Parser};
-use proc_macro2::{token_stream, Delimiter, Ident, Literal, Span, TokenStream, TokenTree};
+use proc_macro2::{token_stream, Delimiter, Ident, Literal, Spacing, Span, TokenStream, TokenTree};
 use proc_macro_error::abort;
 use quote::quote;
 use std::ffi::OsString;
 use std::iter::Peekable;
 
-// Scan string literal to tokenstream, used by most of the macros
-//
-// - support ${var} or $var for interpolation
-//   - to escape '$' itself, use "$$"
-// - support normal rust character escapes:
-//   https://doc.rust-lang.org/reference/tokens.html#ascii-escapes
+/// Scan string literal to tokenstream, used by most of the macros
+///
+/// - support ${var} or $var for interpolation
+///   - to escape '$' itself, use "$$"
+/// - support normal rust character escapes:
+///   https://doc.rust-lang.org/reference/tokens.html#ascii-escapes
+/// - an identifier glued after the closing quote, which rust lexes as a literal suffix like
+///   `"$v"post`, is appended as is
+///
+/// Unbalanced or empty `${...}` fails to compile:
+/// ```compile_fail
+/// # use cmd_lib::run_cmd;
+/// let msg = "hello";
+/// run_cmd!(echo "${msg").unwrap();
+/// ```
+/// ```compile_fail
+/// # use cmd_lib::run_cmd;
+/// run_cmd!(echo "${}").unwrap();
+/// ```
+/// ```compile_fail
+/// # use cmd_lib::run_cmd;
+/// let msg = "hello";
+/// run_cmd!(echo "${ msg }").unwrap();
+/// ```
+/// ```compile_fail
+/// # use cmd_lib::run_cmd;
+/// run_cmd!(echo "${0msg}").unwrap();
+/// ```
+/// ```
+/// # use cmd_lib::run_fun;
+/// let msg = "hello";
+/// assert_eq!(run_fun!(echo "${msg}$}$$").unwrap(), "hello$}$");
+/// ```
 pub fn scan_str_lit(lit: &Literal) -> TokenStream {
-    let s = lit.to_string();
-    if !s.starts_with('\"') {
-        return quote!(::cmd_lib::CmdString::from(#lit));
+    let (s, suffix) = split_suffix(lit);
+    let mut output = if !s.starts_with('\"') {
+        let mut unsuffixed = syn::parse_str::<Literal>(&s).unwrap();
+        unsuffixed.set_span(lit.span());
+        quote!(::cmd_lib::CmdString::from(#unsuffixed))
+    } else {
+        scan_interpolation(&s[1..s.len() - 1], false, lit) // To trim outside ""
+    };
+    if !suffix.is_empty() {
+        output.extend(quote!(.append(#suffix)));
     }
-    let mut iter = s[1..s.len() - 1] // To trim outside ""
-        .chars()
-        .peekable();
+    output
+}
+
+// Scan raw string literal with the same interpolation, used by `$r"..."`, and
+// there are no character escapes
+pub fn scan_raw_str_lit(lit: &Literal) -> TokenStream {
+    let (s, suffix) = split_suffix(lit);
+    let hashes = s[1..].chars().take_while(|c| *c == '#').count();
+    let mut output = scan_interpolation(&s[hashes + 2..s.len() - hashes - 1], true, lit); // To trim r#""#
+    if !suffix.is_empty() {
+        output.extend(quote!(.append(#suffix)));
+    }
+    output
+}
+
+// Split a string literal into the quoted part and its suffix, which can not contain quotes or '#'
+fn split_suffix(lit: &Literal) -> (String, String) {
+    let mut s = lit.to_string();
+    let suffix = s.split_off(s.rfind(['"', '#']).map_or(s.len(), |i| i + 1));
+    (s, suffix)
+}
+
+fn scan_interpolation(content: &str, raw: bool, lit: &Literal) -> TokenStream {
+    let mut iter = content.chars().peekable();
     let mut output = quote!(::cmd_lib::CmdString::default());
     let mut last_part = OsString::new();
-    fn seal_last_part(last_part: &mut OsString, output: &mut TokenStream) {
+    let seal_last_part = |last_part: &mut OsString, output: &mut TokenStream| {
         if !last_part.is_empty() {
-            let lit_str = format!("\"{}\"", last_part.to_str().unwrap());
-            let l = syn::parse_str::<Literal>(&lit_str).unwrap();
+            let l = if raw {
+                Literal::string(last_part.to_str().unwrap())
+            } else {
+                let lit_str = format!("\"{}\"", last_part.to_str().unwrap());
+                syn::parse_str::<Literal>(&lit_str).unwrap()
+            };
             output.extend(quote!(.append(#l)));
             last_part.clear();
         }
-    }
+    };
 
     while let Some(ch) = iter.next() {
         if ch == '$' {
@@ -56,10 +115,22 @@ pub fn scan_str_lit(lit: &Literal) -> TokenStream {
                 iter.next();
             }
             if with_brace {
-                if iter.peek() != Some(&'}') {
-                    abort!(lit.span(), "bad substitution");
-                } else {
-                    iter.next();
+                match iter.peek() {
+                    Some('}') if var.is_empty() => {
+                        abort!(
+                            lit.span(),
+                            "bad substitution: empty variable name in \"${}\""
+                        )
+                    }
+                    Some('}') => {
+                        iter.next();
+                    }
+                    Some(c) => abort!(
+                        lit.span(),
+                        "bad substitution: invalid character {:?} in \"${{...}}\"",
+                        c
+                    ),
+                    None => abort!(lit.span(), "bad substitution: unmatched \"${\""),
                 }
             }
             if !var.is_empty() {
@@ -84,26 +155,36 @@ enum SepToken {
 
 enum RedirectFd {
     Stdin,
-    Stdout { append: bool },
-    Stderr { append: bool },
-    StdoutErr { append: bool },
+    HereDoc,
+    Stdout { append: bool, force: bool },
+    Stderr { append: bool, force: bool },
+    StdoutErr { append: bool, force: bool },
 }
 
 pub struct Lexer {
     iter: TokenStreamPeekable<token_stream::IntoIter>,
     args: Vec<ParseArg>,
-    last_arg_str: TokenStream,
+    // more than one when the arg is fanned out by brace expansion
+    last_arg_strs: Vec<TokenStream>,
+    last_arg_span: Span,
     last_redirect: Option<(RedirectFd, Span)>,
+    last_in_dir: Option<Span>,
+    last_label: Option<Span>,
     seen_redirect: (bool, bool, bool),
+    has_substitution: bool,
 }
 
 impl Lexer {
     pub fn new(input: TokenStream) -> Self {
         Self {
             args: vec![],
-            last_arg_str: TokenStream::new(),
+            last_arg_strs: vec![],
+            last_arg_span: Span::call_site(),
             last_redirect: None,
+            last_in_dir: None,
+            last_label: None,
             seen_redirect: (false, false, false),
+            has_substitution: false,
             iter: TokenStreamPeekable {
                 peekable: input.into_iter().peekable(),
                 span: Span::call_site(),
@@ -114,15 +195,32 @@ impl Lexer {
     pub fn scan(mut self) -> Parser<impl Iterator<Item = ParseArg>> {
         while let Some(item) = self.iter.next() {
             match item {
-                TokenTree::Group(_) => {
-                    abort!(self.iter.span(), "grouping is only allowed for variables");
+                TokenTree::Group(g) => {
+                    if g.delimiter() != Delimiter::Brace {
+                        abort!(self.iter.span(), "grouping is only allowed for variables");
+                    }
+                    self.scan_brace(g.stream());
                 }
                 TokenTree::Literal(lit) => {
                     self.scan_literal(lit);
                 }
                 TokenTree::Ident(ident) => {
                     let s = ident.to_string();
-                    self.extend_last_arg(quote!(#s));
+                    if s == "in" && self.at_cmd_start() && self.iter.peek_no_gap().is_none() {
+                        // `in <dir> cmd ...` runs this command only in <dir>
+                        self.last_in_dir = Some(ident.span());
+                    } else if s == "label" && self.at_cmd_start() && self.peek_str_lit() {
+                        // `label "name" -- cmd ...` prefixes the output lines of this command
+                        self.last_label = Some(ident.span());
+                    } else if s == "umask" && self.at_cmd_start() && self.peek_number() {
+                        // `umask 022 cmd ...` sets the file mode creation mask for this command
+                        self.scan_umask();
+                    } else if s == "when" && self.at_statement_start() && self.peek_dollar() {
+                        // `when $cond cmd ...` runs this statement only if cond is true
+                        self.scan_condition();
+                    } else {
+                        self.extend_last_arg(quote!(#s));
+                    }
                 }
                 TokenTree::Punct(punct) => {
                     let ch = punct.as_char();
@@ -131,7 +229,7 @@ impl Lexer {
                     } else if ch == '|' {
                         self.scan_pipe();
                     } else if ch == '<' {
-                        self.set_redirect(self.iter.span(), RedirectFd::Stdin);
+                        self.scan_redirect_in();
                     } else if ch == '>' {
                         self.scan_redirect_out(1);
                     } else if ch == '&' {
@@ -145,38 +243,180 @@ impl Lexer {
                 }
             }
 
-            if self.iter.peek_no_gap().is_none() && !self.last_arg_str.is_empty() {
+            if self.iter.peek_no_gap().is_none() && !self.last_arg_strs.is_empty() {
                 self.add_arg_with_token(SepToken::Space, self.iter.span());
             }
         }
         self.add_arg_with_token(SepToken::Space, self.iter.span());
-        Parser::from(self.args.into_iter().peekable())
+        Parser::from(self.args.into_iter().peekable()).with_substitution(self.has_substitution)
+    }
+
+    fn at_cmd_start(&self) -> bool {
+        self.last_arg_strs.is_empty()
+            && self.last_redirect.is_none()
+            && self.last_in_dir.is_none()
+            && self.last_label.is_none()
+            && matches!(
+                self.args.last(),
+                None | Some(ParseArg::Pipe)
+                    | Some(ParseArg::Semicolon)
+                    | Some(ParseArg::Condition(_))
+                    | Some(ParseArg::Umask(_))
+                    | Some(ParseArg::Label(_))
+            )
+    }
+
+    fn at_statement_start(&self) -> bool {
+        self.at_cmd_start() && matches!(self.args.last(), None | Some(ParseArg::Semicolon))
+    }
+
+    fn peek_dollar(&mut self) -> bool {
+        self.iter.peek_no_gap().is_none()
+            && matches!(self.iter.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '$')
+    }
+
+    fn peek_str_lit(&mut self) -> bool {
+        if self.iter.peek_no_gap().is_some() {
+            return false;
+        }
+        matches!(self.iter.peek(), Some(TokenTree::Literal(lit)) if lit.to_string().starts_with('"'))
+    }
+
+    // consumes a `--` separator if it is next
+    fn scan_double_dash(&mut self) -> bool {
+        match self.iter.peek() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '-' && p.spacing() == Spacing::Joint => {}
+            _ => return false,
+        }
+        let first = self.iter.next().unwrap();
+        match self.iter.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '-' && p.spacing() == Spacing::Alone => {
+                true
+            }
+            _ => abort!(first.span(), "expect `--` between label and command"),
+        }
+    }
+
+    // whether more arguments of the current command follow
+    fn peek_cmd_continues(&mut self) -> bool {
+        match self.iter.peek() {
+            None => false,
+            Some(TokenTree::Punct(p)) => !matches!(p.as_char(), ';' | '|' | '<' | '>' | '&'),
+            Some(_) => true,
+        }
+    }
+
+    fn peek_number(&mut self) -> bool {
+        if self.iter.peek_no_gap().is_some() {
+            return false;
+        }
+        match self.iter.peek() {
+            Some(TokenTree::Literal(lit)) => {
+                lit.to_string().starts_with(|c: char| c.is_ascii_digit())
+            }
+            _ => false,
+        }
+    }
+
+    fn scan_umask(&mut self) {
+        let mode = self.iter.next().unwrap();
+        let mode = match u32::from_str_radix(&mode.to_string(), 8) {
+            Ok(mode) if mode <= 0o777 => mode,
+            _ => abort!(mode.span(), "invalid umask: expect an octal mode like 022"),
+        };
+        if self.iter.peek().is_none() {
+            abort!(self.iter.span(), "expect command after umask");
+        }
+        self.args.push(ParseArg::Umask(mode));
+    }
+
+    fn scan_condition(&mut self) {
+        self.iter.next(); // '$'
+        let cond = match self.iter.peek_no_gap().map(|tt| tt.to_owned()) {
+            Some(TokenTree::Ident(var)) => var,
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                let mut inner = g.stream().into_iter();
+                match (inner.next(), inner.next()) {
+                    (Some(TokenTree::Ident(var)), None) => var,
+                    _ => abort!(g.span(), "invalid condition: expect a bool variable"),
+                }
+            }
+            _ => abort!(
+                self.iter.span(),
+                "invalid condition: expect a bool variable"
+            ),
+        };
+        self.iter.next();
+        if self.iter.peek().is_none() {
+            abort!(cond.span(), "expect command after condition");
+        }
+        self.args.push(ParseArg::Condition(quote!(#cond)));
     }
 
     fn add_arg_with_token(&mut self, token: SepToken, token_span: Span) {
-        let last_arg_str = &self.last_arg_str;
-        if let Some((redirect, span)) = self.last_redirect.take() {
-            if last_arg_str.is_empty() {
+        if let Some(span) = self.last_in_dir.take() {
+            if self.last_arg_strs.is_empty() {
+                abort!(span, "wrong `in` format: missing directory");
+            }
+            let last_arg_str = self.single_last_arg(span);
+            self.args.push(ParseArg::CurrentDir(quote!(#last_arg_str)));
+        } else if let Some(span) = self.last_label.take() {
+            let last_arg_str = self.single_last_arg(span);
+            if matches!(token, SepToken::Space) && self.scan_double_dash() {
+                if !self.peek_cmd_continues() {
+                    abort!(span, "wrong `label` format: missing command after `--`");
+                }
+                self.args.push(ParseArg::Label(quote!(#last_arg_str)));
+            } else {
+                if matches!(token, SepToken::Space) && self.peek_cmd_continues() {
+                    // a command after the label would silently become part of the label text
+                    abort!(
+                        span,
+                        "wrong `label` format: expect `label \"<label>\" -- <cmd>` to label a command"
+                    );
+                }
+                // without a command, it is the builtin `label` command
+                self.args.push(ParseArg::ArgStr(quote!("label"), span));
+                self.args
+                    .push(ParseArg::ArgStr(quote!(#last_arg_str), self.last_arg_span));
+            }
+        } else if let Some((redirect, span)) = self.last_redirect.take() {
+            if self.last_arg_strs.is_empty() {
                 abort!(span, "wrong redirection format: missing target");
             }
+            let last_arg_str = self.single_last_arg(span);
 
             let mut stdouterr = false;
-            let (fd, append) = match redirect {
-                RedirectFd::Stdin => (0, false),
-                RedirectFd::Stdout { append } => (1, append),
-                RedirectFd::Stderr { append } => (2, append),
-                RedirectFd::StdoutErr { append } => {
+            let (fd, append, force) = match redirect {
+                RedirectFd::HereDoc => {
+                    self.args.push(ParseArg::HereDoc(quote!(#last_arg_str)));
+                    (-1, false, false)
+                }
+                RedirectFd::Stdin => (0, false, false),
+                RedirectFd::Stdout { append, force } => (1, append, force),
+                RedirectFd::Stderr { append, force } => (2, append, force),
+                RedirectFd::StdoutErr { append, force } => {
                     stdouterr = true;
-                    (1, append)
+                    (1, append, force)
                 }
             };
-            self.args
-                .push(ParseArg::RedirectFile(fd, quote!(#last_arg_str), append));
+            if fd >= 0 {
+                self.args.push(ParseArg::RedirectFile(
+                    fd,
+                    quote!(#last_arg_str),
+                    append,
+                    force,
+                    span,
+                ));
+            }
             if stdouterr {
-                self.args.push(ParseArg::RedirectFd(2, 1));
+                self.args.push(ParseArg::RedirectFd(2, 1, span));
+            }
+        } else {
+            for last_arg_str in self.last_arg_strs.iter() {
+                self.args
+                    .push(ParseArg::ArgStr(quote!(#last_arg_str), self.last_arg_span));
             }
-        } else if !last_arg_str.is_empty() {
-            self.args.push(ParseArg::ArgStr(quote!(#last_arg_str)));
         }
         let mut new_redirect = (false, false, false);
         match token {
@@ -189,14 +429,88 @@ impl Lexer {
             }
         }
         self.seen_redirect = new_redirect;
-        self.last_arg_str = TokenStream::new();
+        self.last_arg_strs.clear();
+    }
+
+    fn single_last_arg(&self, span: Span) -> TokenStream {
+        if self.last_arg_strs.len() > 1 {
+            abort!(
+                span,
+                "ambiguous target: brace expansion to multiple arguments"
+            );
+        }
+        self.last_arg_strs[0].clone()
+    }
+
+    fn start_last_arg(&mut self) {
+        if self.last_arg_strs.is_empty() {
+            self.last_arg_span = self.iter.span();
+            self.last_arg_strs = vec![quote!(::cmd_lib::CmdString::default())];
+        }
     }
 
     fn extend_last_arg(&mut self, stream: TokenStream) {
-        if self.last_arg_str.is_empty() {
-            self.last_arg_str = quote!(::cmd_lib::CmdString::default());
+        self.start_last_arg();
+        for last_arg_str in self.last_arg_strs.iter_mut() {
+            last_arg_str.extend(quote!(.append(#stream)));
+        }
+    }
+
+    // Fan out `{a,b,c}` into one arg for each alternative, like brace expansion in bash, which
+    // is pure string expansion without touching the filesystem. Braces without any comma, and
+    // nested ones, are left literal.
+    fn scan_brace(&mut self, stream: TokenStream) {
+        let mut alts = vec![String::new()];
+        for tt in stream {
+            match tt {
+                TokenTree::Punct(ref p) if p.as_char() == ',' => alts.push(String::new()),
+                TokenTree::Punct(ref p) if p.as_char() == '$' => {
+                    abort!(p.span(), "variables are not allowed in brace expansion");
+                }
+                TokenTree::Literal(ref lit) if lit.to_string().starts_with(['"', 'r']) => {
+                    abort!(
+                        lit.span(),
+                        "string literals are not allowed in brace expansion"
+                    );
+                }
+                _ => alts.last_mut().unwrap().push_str(&Self::literal_str(&tt)),
+            }
+        }
+        if alts.len() == 1 {
+            let s = format!("{{{}}}", alts[0]);
+            self.extend_last_arg(quote!(#s));
+            return;
+        }
+
+        self.start_last_arg();
+        self.last_arg_strs = self
+            .last_arg_strs
+            .iter()
+            .flat_map(|last_arg_str| {
+                alts.iter()
+                    .map(move |alt| quote!(#last_arg_str.append(#alt)))
+            })
+            .collect();
+    }
+
+    fn literal_str(tt: &TokenTree) -> String {
+        match tt {
+            TokenTree::Group(g) => {
+                let (open, close) = match g.delimiter() {
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::None => ("", ""),
+                };
+                let inner: String = g
+                    .stream()
+                    .into_iter()
+                    .map(|tt| Self::literal_str(&tt))
+                    .collect();
+                format!("{open}{inner}{close}")
+            }
+            _ => tt.to_string(),
         }
-        self.last_arg_str.extend(quote!(.append(#stream)));
     }
 
     fn check_set_redirect(redirect: &mut bool, name: &str, span: Span) {
@@ -211,14 +525,16 @@ impl Lexer {
             abort!(span, "wrong double redirection format");
         }
         match fd {
-            RedirectFd::Stdin => Self::check_set_redirect(&mut self.seen_redirect.0, "stdin", span),
-            RedirectFd::Stdout { append: _ } => {
+            RedirectFd::Stdin | RedirectFd::HereDoc => {
+                Self::check_set_redirect(&mut self.seen_redirect.0, "stdin", span)
+            }
+            RedirectFd::Stdout { .. } => {
                 Self::check_set_redirect(&mut self.seen_redirect.1, "stdout", span)
             }
-            RedirectFd::Stderr { append: _ } => {
+            RedirectFd::Stderr { .. } => {
                 Self::check_set_redirect(&mut self.seen_redirect.2, "stderr", span)
             }
-            RedirectFd::StdoutErr { append: _ } => {
+            RedirectFd::StdoutErr { .. } => {
                 Self::check_set_redirect(&mut self.seen_redirect.1, "stdout", span);
                 Self::check_set_redirect(&mut self.seen_redirect.2, "stderr", span);
             }
@@ -255,18 +571,17 @@ impl Lexer {
                 if let Some(ref redirect) = self.last_redirect {
                     abort!(redirect.1, "invalid '&': found previous redirect");
                 }
-                Self::check_set_redirect(&mut self.seen_redirect.2, "stderr", p.span());
-                self.args.push(ParseArg::RedirectFd(2, 1));
+                let span = p.span();
+                Self::check_set_redirect(&mut self.seen_redirect.2, "stderr", span);
+                self.args.push(ParseArg::RedirectFd(2, 1, span));
                 self.iter.next();
             }
         }
 
         // expect new command
         match self.iter.peek() {
-            Some(TokenTree::Punct(np)) => {
-                if np.as_char() == '|' || np.as_char() == ';' {
-                    abort!(np.span(), "expect new command after '|'");
-                }
+            Some(TokenTree::Punct(np)) if np.as_char() == '|' || np.as_char() == ';' => {
+                abort!(np.span(), "expect new command after '|'");
             }
             None => {
                 abort!(self.iter.span(), "expect new command after '|'");
@@ -276,14 +591,27 @@ impl Lexer {
         self.add_arg_with_token(SepToken::Pipe, self.iter.span());
     }
 
+    fn scan_redirect_in(&mut self) {
+        let span = self.iter.span();
+        if let Some(TokenTree::Punct(p)) = self.iter.peek_no_gap() {
+            if p.as_char() == '<' {
+                // `<<` takes the following string as stdin content
+                self.iter.next();
+                self.set_redirect(span, RedirectFd::HereDoc);
+                return;
+            }
+        }
+        self.set_redirect(span, RedirectFd::Stdin);
+    }
+
     fn scan_redirect_out(&mut self, fd: i32) {
-        let append = self.check_append();
+        let (append, force) = self.check_write_mode();
         self.set_redirect(
             self.iter.span(),
             if fd == 1 {
-                RedirectFd::Stdout { append }
+                RedirectFd::Stdout { append, force }
             } else {
-                RedirectFd::Stderr { append }
+                RedirectFd::Stderr { append, force }
             },
         );
         if let Some(TokenTree::Punct(p)) = self.iter.peek_no_gap() {
@@ -291,6 +619,9 @@ impl Lexer {
                 if append {
                     abort!(p.span(), "raw fd not allowed for append redirection");
                 }
+                if force {
+                    abort!(p.span(), "raw fd not allowed for force redirection");
+                }
                 self.iter.next();
                 if let Some(TokenTree::Literal(lit)) = self.iter.peek_no_gap() {
                     let s = lit.to_string();
@@ -298,9 +629,9 @@ impl Lexer {
                         abort!(lit.span(), "invalid literal string after &");
                     }
                     if &s == "1" {
-                        self.args.push(ParseArg::RedirectFd(fd, 1));
+                        self.args.push(ParseArg::RedirectFd(fd, 1, lit.span()));
                     } else if &s == "2" {
-                        self.args.push(ParseArg::RedirectFd(fd, 2));
+                        self.args.push(ParseArg::RedirectFd(fd, 2, lit.span()));
                     } else {
                         abort!(lit.span(), "Only &1 or &2 is supported");
                     }
@@ -319,8 +650,8 @@ impl Lexer {
                 let span = p.span();
                 if p.as_char() == '>' {
                     self.iter.next();
-                    let append = self.check_append();
-                    self.set_redirect(span, RedirectFd::StdoutErr { append });
+                    let (append, force) = self.check_write_mode();
+                    self.set_redirect(span, RedirectFd::StdoutErr { append, force });
                 } else {
                     abort!(span, "invalid punctuation");
                 }
@@ -344,7 +675,22 @@ impl Lexer {
         // let peek_no_gap = None;
         if let Some(TokenTree::Ident(var)) = peek_no_gap {
             self.extend_last_arg(quote!(#var.as_os_str()));
+        } else if let Some(TokenTree::Literal(ref lit)) = peek_no_gap {
+            if !lit.to_string().starts_with('r') {
+                abort!(
+                    lit.span(),
+                    "invalid literal after $: only raw string is allowed"
+                );
+            }
+            // `$r"..."` interpolates variables in a raw string
+            let ss = scan_raw_str_lit(lit);
+            self.extend_last_arg(quote!(#ss.into_os_string()));
         } else if let Some(TokenTree::Group(g)) = peek_no_gap {
+            if g.delimiter() == Delimiter::Parenthesis {
+                self.scan_substitution(g.stream(), g.span());
+                self.iter.next();
+                return;
+            }
             if g.delimiter() != Delimiter::Brace && g.delimiter() != Delimiter::Bracket {
                 abort!(
                     g.span(),
@@ -352,6 +698,22 @@ impl Lexer {
                     format!("{:?}", g.delimiter()).to_lowercase()
                 );
             }
+            let mut inner = g.stream().into_iter();
+            if let (Some(TokenTree::Group(sub)), None) = (inner.next(), inner.next()) {
+                if g.delimiter() == Delimiter::Bracket && sub.delimiter() == Delimiter::Parenthesis
+                {
+                    self.scan_split_substitution(sub.stream(), sub.span());
+                    self.iter.next();
+                    return;
+                }
+            }
+            if g.delimiter() == Delimiter::Bracket {
+                if let Some((var, sep)) = Self::scan_join(g.stream(), g.span()) {
+                    self.extend_last_arg(quote!((&#var).join_os_args(#sep)));
+                    self.iter.next();
+                    return;
+                }
+            }
             let mut found_var = false;
             for tt in g.stream() {
                 let span = tt.span();
@@ -362,10 +724,11 @@ impl Lexer {
                     if g.delimiter() == Delimiter::Brace {
                         self.extend_last_arg(quote!(#var.as_os_str()));
                     } else {
-                        if !self.last_arg_str.is_empty() {
+                        if !self.last_arg_strs.is_empty() {
                             abort!(span, "vector variable can only be used alone");
                         }
-                        self.args.push(ParseArg::ArgVec(quote!(#var)));
+                        self.args
+                            .push(ParseArg::ArgVec(quote!(#var.into_os_args()), span));
                     }
                     found_var = true;
                 } else {
@@ -378,15 +741,71 @@ impl Lexer {
         self.iter.next();
     }
 
-    fn check_append(&mut self) -> bool {
-        let mut append = false;
+    // `$[var; ","]` joins the vector elements with the separator into a single argument, like
+    // `--features=$[features; ","]`, instead of passing one argument per element
+    fn scan_join(stream: TokenStream, span: Span) -> Option<(Ident, Literal)> {
+        let tokens: Vec<TokenTree> = stream.into_iter().collect();
+        if !tokens
+            .iter()
+            .any(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ';'))
+        {
+            return None;
+        }
+        match tokens.as_slice() {
+            [TokenTree::Ident(var), TokenTree::Punct(p), TokenTree::Literal(sep)]
+                if p.as_char() == ';' && sep.to_string().starts_with('"') =>
+            {
+                Some((var.clone(), sep.clone()))
+            }
+            _ => abort!(span, "invalid joining: expect $[var; \"separator\"]"),
+        }
+    }
+
+    // `$(cmd)` command substitution, run right before its statement in the same directory and
+    // with the same exported variables, and its output without trailing newlines is used as a
+    // single argument, even with multiple lines
+    fn scan_substitution(&mut self, stream: TokenStream, span: Span) {
+        if stream.is_empty() {
+            abort!(span, "empty command substitution");
+        }
+        let cmds = Lexer::new(stream).scan().parse(false);
+        self.extend_last_arg(
+            quote!(#cmds.in_scope(__cmd_lib_scope).run_fun()?.trim_end_matches('\n')),
+        );
+        self.has_substitution = true;
+    }
+
+    // `$[(cmd)]` command substitution, with the output split into arguments on whitespaces
+    fn scan_split_substitution(&mut self, stream: TokenStream, span: Span) {
+        if stream.is_empty() {
+            abort!(span, "empty command substitution");
+        }
+        if !self.last_arg_strs.is_empty() {
+            abort!(span, "split command substitution can only be used alone");
+        }
+        let cmds = Lexer::new(stream).scan().parse(false);
+        self.args.push(ParseArg::ArgVec(
+            quote!(#cmds
+                .in_scope(__cmd_lib_scope)
+                .run_fun()?
+                .split_whitespace()),
+            span,
+        ));
+        self.has_substitution = true;
+    }
+
+    // `>>` appends to the file, and `>|` overwrites it even in noclobber mode
+    fn check_write_mode(&mut self) -> (bool, bool) {
+        let mut mode = (false, false);
         if let Some(TokenTree::Punct(p)) = self.iter.peek_no_gap() {
-            if p.as_char() == '>' {
-                append = true;
-                self.iter.next();
+            match p.as_char() {
+                '>' => mode.0 = true,
+                '|' => mode.1 = true,
+                _ => return mode,
             }
+            self.iter.next();
         }
-        append
+        mode
     }
 }
 
@@ -1,4 +1,4 @@
-use proc_macro2::{TokenStream, TokenTree};
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
 use proc_macro_error::{abort, proc_macro_error};
 use quote::quote;
 
@@ -13,7 +13,7 @@ use quote::quote;
 ///     Ok(())
 /// }
 /// // output:
-/// // [ERROR] FATAL: Running ["bad_cmd"] failed: No such file or directory (os error 2)
+/// // [ERROR] FATAL: Running ["bad_cmd"] failed: command not found: bad_cmd
 /// ```
 #[proc_macro_attribute]
 pub fn main(
@@ -79,6 +79,50 @@ pub fn use_custom_cmd(item: proc_macro::TokenStream) -> proc_macro::TokenStream
     .into()
 }
 
+/// Mark a function as custom command, named after the function, to be registered together with
+/// all the other marked ones by [`register_all_commands()`](../cmd_lib/fn.register_all_commands.html).
+/// ```no_run
+/// # use cmd_lib::*;
+/// # use std::io::Write;
+/// #[cmd_lib::command]
+/// fn my_cmd(env: &mut CmdEnv) -> CmdResult {
+///     writeln!(env.stdout(), "bar")
+/// }
+///
+/// register_all_commands();
+/// run_cmd!(my_cmd)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn command(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let args: TokenStream = args.into();
+    if let Some(t) = args.into_iter().next() {
+        abort!(
+            t,
+            "no arguments expected, the command is named after the function"
+        );
+    }
+    let cmd_fn: syn::ItemFn = match syn::parse2(item.into()) {
+        Ok(cmd_fn) => cmd_fn,
+        Err(e) => abort!(e.span(), "expect a custom command function"),
+    };
+    let cmd = &cmd_fn.sig.ident;
+    let cmd_name = cmd.to_string();
+
+    quote! (
+        #cmd_fn
+
+        ::cmd_lib::inventory::submit! {
+            ::cmd_lib::CustomCmd { name: #cmd_name, func: #cmd }
+        }
+    )
+    .into()
+}
+
 /// Run commands, returning [`CmdResult`](../cmd_lib/type.CmdResult.html) to check status.
 /// ```no_run
 /// # use cmd_lib::run_cmd;
@@ -108,10 +152,11 @@ pub fn use_custom_cmd(item: proc_macro::TokenStream) -> proc_macro::TokenStream
 #[proc_macro]
 #[proc_macro_error]
 pub fn run_cmd(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let cmds = lexer::Lexer::new(input.into()).scan().parse(false);
+    let (modifiers, input) = parse_modifiers(input.into());
+    let cmds = lexer::Lexer::new(input).scan().parse(false);
     quote! ({
-        use ::cmd_lib::AsOsStr;
-        #cmds.run_cmd()
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds #modifiers.run_cmd()
     })
     .into()
 }
@@ -130,10 +175,51 @@ pub fn run_cmd(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 #[proc_macro]
 #[proc_macro_error]
 pub fn run_fun(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let cmds = lexer::Lexer::new(input.into()).scan().parse(false);
+    let (modifiers, input) = parse_modifiers(input.into());
+    let cmds = lexer::Lexer::new(input).scan().parse(false);
+    quote! ({
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds #modifiers.run_fun()
+    })
+    .into()
+}
+
+/// Run commands like [`run_fun!`](../cmd_lib/macro.run_fun.html), but keep the output as is,
+/// without trimming the trailing newline.
+/// ```no_run
+/// # use cmd_lib::run_fun_untrimmed;
+/// let config = run_fun_untrimmed!(cat /etc/hosts)?;
+/// assert!(config.is_empty() || config.ends_with('\n'));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro]
+#[proc_macro_error]
+pub fn run_fun_untrimmed(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let (modifiers, input) = parse_modifiers(input.into());
+    let cmds = lexer::Lexer::new(input).scan().parse(false);
     quote! ({
-        use ::cmd_lib::AsOsStr;
-        #cmds.run_fun()
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds #modifiers.run_fun_untrimmed()
+    })
+    .into()
+}
+
+/// Run commands like [`run_fun!`](../cmd_lib/macro.run_fun.html), but strip ANSI escape
+/// sequences, like colors, from the output, to parse the output of colored tools.
+/// ```no_run
+/// # use cmd_lib::run_fun_plain;
+/// let branches = run_fun_plain!(git branch --color=always)?;
+/// assert!(!branches.contains('\x1b'));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro]
+#[proc_macro_error]
+pub fn run_fun_plain(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let (modifiers, input) = parse_modifiers(input.into());
+    let cmds = lexer::Lexer::new(input).scan().parse(false);
+    quote! ({
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds #modifiers.run_fun_plain()
     })
     .into()
 }
@@ -153,7 +239,7 @@ pub fn run_fun(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 pub fn spawn(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let cmds = lexer::Lexer::new(input.into()).scan().parse(true);
     quote! ({
-        use ::cmd_lib::AsOsStr;
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
         #cmds.spawn(false)
     })
     .into()
@@ -182,12 +268,83 @@ pub fn spawn(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 pub fn spawn_with_output(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let cmds = lexer::Lexer::new(input.into()).scan().parse(true);
     quote! ({
-        use ::cmd_lib::AsOsStr;
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
         #cmds.spawn_with_output()
     })
     .into()
 }
 
+/// Run commands with/without pipes as a child process like
+/// [`spawn_with_output!`](../cmd_lib/macro.spawn_with_output.html), with the stdin of the first
+/// command connected to a pipe, to write to it with
+/// [`FunChildren::take_stdin()`](../cmd_lib/struct.FunChildren.html#method.take_stdin).
+/// ```no_run
+/// # use cmd_lib::*;
+/// # use std::io::{BufRead, BufReader, Write};
+/// let mut proc = spawn_with_stdin!(bc -q)?;
+/// let mut stdin = proc.take_stdin();
+/// proc.wait_with_pipe(&mut |stdout| {
+///     let mut stdin = stdin.take().unwrap(); // closed when dropped at the end
+///     let mut lines = BufReader::new(stdout).lines();
+///     for expr in ["1 + 2", "3 * 4"] {
+///         writeln!(stdin, "{expr}").unwrap();
+///         println!("{expr} = {}", lines.next().unwrap().unwrap());
+///     }
+/// })?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro]
+#[proc_macro_error]
+pub fn spawn_with_stdin(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let cmds = lexer::Lexer::new(input.into()).scan().parse(true);
+    quote! ({
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds.spawn_with_stdin()
+    })
+    .into()
+}
+
+/// Run a single command under a pseudo-terminal, returning [`FunResult`](../cmd_lib/type.FunResult.html)
+/// with its combined stdout and stderr output. Available with the `pty` feature, on unix only.
+///
+/// Tools which check for a terminal, to print colors or progress bars, behave as if they run
+/// interactively. Nothing is written to the terminal input.
+/// ```no_run
+/// # use cmd_lib::*;
+/// let output = run_cmd_pty!(ls --color=auto /)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro]
+#[proc_macro_error]
+pub fn run_cmd_pty(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let cmds = lexer::Lexer::new(input.into()).scan().parse(true);
+    quote! ({
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds.run_pty()
+    })
+    .into()
+}
+
+/// Run a single command under a pseudo-terminal as a child process, returning
+/// [`FunChildren`](../cmd_lib/struct.FunChildren.html) result, with its combined stdout and stderr
+/// output. Available with the `pty` feature, on unix only.
+/// ```no_run
+/// # use cmd_lib::*;
+/// let mut proc = spawn_pty!(cargo build)?;
+/// let output = proc.wait_with_output()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[proc_macro]
+#[proc_macro_error]
+pub fn spawn_pty(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let cmds = lexer::Lexer::new(input.into()).scan().parse(true);
+    quote! ({
+        use ::cmd_lib::{AsOsStr, AsOsStrExt, IntoOsArgs, IntoOsArgsExt};
+        #cmds.spawn_pty()
+    })
+    .into()
+}
+
 #[proc_macro]
 #[proc_macro_error]
 /// Log a fatal message at the error level, and exit process.
@@ -213,6 +370,56 @@ pub fn cmd_die(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     .into()
 }
 
+// parses the leading `#[...]` modifiers, like `#[timeout(30s)]`, returning the calls to apply them
+// on the commands, and the rest of the input
+fn parse_modifiers(input: TokenStream) -> (TokenStream, TokenStream) {
+    let mut iter = input.into_iter().peekable();
+    let mut modifiers = TokenStream::new();
+    while matches!(iter.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '#') {
+        let pound = iter.next().unwrap();
+        let attr = match iter.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => g,
+            _ => abort!(pound, "expect modifier like #[timeout(30s)]"),
+        };
+        let mut attr_iter = attr.stream().into_iter();
+        match (attr_iter.next(), attr_iter.next(), attr_iter.next()) {
+            (Some(TokenTree::Ident(name)), Some(TokenTree::Group(args)), None)
+                if name == "timeout" && args.delimiter() == Delimiter::Parenthesis =>
+            {
+                let timeout = parse_duration(args.stream(), args.span());
+                modifiers.extend(quote!(.timeout(#timeout)));
+            }
+            _ => abort!(attr, "unknown modifier, expect #[timeout(<duration>)]"),
+        }
+    }
+    (modifiers, iter.collect())
+}
+
+// parses a duration literal like `500ms`, `30s`, `1.5m` or `2h`, or takes any other expression
+// as a `Duration` value
+fn parse_duration(input: TokenStream, span: proc_macro2::Span) -> TokenStream {
+    let mut iter = input.clone().into_iter();
+    let lit = match (iter.next(), iter.next()) {
+        (Some(TokenTree::Literal(lit)), None) => lit,
+        (Some(_), _) => return input,
+        (None, _) => abort!(span, "missing duration"),
+    };
+    let s = lit.to_string();
+    let (num, unit) = s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+    let factor = match unit {
+        "ms" => 1.0,
+        "s" => 1000.0,
+        "m" => 60_000.0,
+        "h" => 3_600_000.0,
+        _ => abort!(lit, "invalid duration unit: expect ms, s, m or h"),
+    };
+    let millis = match num.replace('_', "").parse::<f64>() {
+        Ok(n) if n.is_finite() && n >= 0.0 => (n * factor).round() as u64,
+        _ => abort!(lit, "invalid duration: {}", s),
+    };
+    quote!(::std::time::Duration::from_millis(#millis))
+}
+
 fn parse_msg(input: TokenStream) -> TokenStream {
     let mut iter = input.into_iter();
     let mut output = TokenStream::new();
@@ -1,32 +1,63 @@
-use proc_macro2::TokenStream;
-use quote::quote;
+use proc_macro2::{Span, TokenStream};
+use proc_macro_error::abort;
+use quote::{quote, quote_spanned};
 use std::iter::Peekable;
 
 #[derive(Debug)]
 pub enum ParseArg {
     Pipe,
     Semicolon,
-    RedirectFd(i32, i32),                 // fd1, fd2
-    RedirectFile(i32, TokenStream, bool), // fd1, file, append?
-    ArgStr(TokenStream),
-    ArgVec(TokenStream),
+    RedirectFd(i32, i32, Span),                       // fd1, fd2
+    RedirectFile(i32, TokenStream, bool, bool, Span), // fd1, file, append?, force?
+    HereDoc(TokenStream),                             // stdin content
+    CurrentDir(TokenStream),                          // current directory for this command only
+    Condition(TokenStream),                           // run this statement only if true
+    Umask(u32),                                       // file mode creation mask for this command
+    Label(TokenStream),                               // prefix of output lines of this command
+    ArgStr(TokenStream, Span),
+    ArgVec(TokenStream, Span),
 }
 
 pub struct Parser<I: Iterator<Item = ParseArg>> {
     iter: Peekable<I>,
+    with_substitution: bool,
+    conditions: Vec<TokenStream>, // `when` conditions of the current statement
 }
 
 impl<I: Iterator<Item = ParseArg>> Parser<I> {
     pub fn from(iter: Peekable<I>) -> Self {
-        Self { iter }
+        Self {
+            iter,
+            with_substitution: false,
+            conditions: vec![],
+        }
+    }
+
+    pub fn with_substitution(mut self, with_substitution: bool) -> Self {
+        self.with_substitution = with_substitution;
+        self
     }
 
     pub fn parse(mut self, for_spawn: bool) -> TokenStream {
         let mut ret = quote!(::cmd_lib::GroupCmds::default());
         while self.iter.peek().is_some() {
             let cmd = self.parse_cmd();
+            let conditions = std::mem::take(&mut self.conditions);
             if !cmd.is_empty() {
-                ret.extend(quote!(.append(#cmd)));
+                if self.with_substitution {
+                    // command substitutions run only when their own statement runs, and never
+                    // for a statement skipped by `when`
+                    ret.extend(quote!(.append_lazy(
+                        |__cmd_lib_scope: &::cmd_lib::Scope| -> ::std::io::Result<::cmd_lib::Cmds> {
+                            #(if !(#conditions) {
+                                return Ok(::cmd_lib::Cmds::skipped());
+                            })*
+                            Ok(#cmd)
+                        }
+                    )));
+                } else {
+                    ret.extend(quote!(.append(#cmd)));
+                }
                 assert!(
                     !(for_spawn && self.iter.peek().is_some()),
                     "wrong spawning format: group command not allowed"
@@ -51,45 +82,72 @@ impl<I: Iterator<Item = ParseArg>> Parser<I> {
     }
 
     fn parse_pipe(&mut self) -> TokenStream {
-        // TODO: get accurate line number once `proc_macro::Span::line()` API is stable
-        let mut ret = quote!(::cmd_lib::Cmd::default().with_location(file!(), line!()));
+        let mut ret = TokenStream::new();
+        // span of the first token of this command, for accurate line number
+        let mut cmd_span = None;
         while let Some(arg) = self.iter.peek() {
             match arg {
-                ParseArg::RedirectFd(fd1, fd2) => {
-                    if fd1 != fd2 {
+                ParseArg::RedirectFd(fd1, fd2, span) => {
+                    cmd_span.get_or_insert(*span);
+                    if *fd1 == 2 && *fd2 == 2 {
+                        // `2>&2` keeps stderr on the parent's stderr, instead of logging it
+                        ret.extend(quote!(.inherit_stderr()));
+                    } else if fd1 != fd2 {
                         let mut redirect = quote!(::cmd_lib::Redirect);
                         match (fd1, fd2) {
                             (1, 2) => redirect.extend(quote!(::StdoutToStderr)),
                             (2, 1) => redirect.extend(quote!(::StderrToStdout)),
-                            _ => panic!("unsupported fd numbers: {} {}", fd1, fd2),
+                            _ => abort!(*span, "unsupported fd numbers: {} {}", fd1, fd2),
                         }
                         ret.extend(quote!(.add_redirect(#redirect)));
                     }
                 }
-                ParseArg::RedirectFile(fd1, file, append) => {
+                ParseArg::RedirectFile(fd1, file, append, force, span) => {
+                    cmd_span.get_or_insert(*span);
                     let mut redirect = quote!(::cmd_lib::Redirect);
                     match fd1 {
                         0 => redirect.extend(quote!(::FileToStdin(#file.into_path_buf()))),
-                        1 => {
-                            redirect.extend(quote!(::StdoutToFile(#file.into_path_buf(), #append)))
-                        }
-                        2 => {
-                            redirect.extend(quote!(::StderrToFile(#file.into_path_buf(), #append)))
-                        }
-                        _ => panic!("unsupported fd ({}) redirect to file {}", fd1, file),
+                        1 => redirect
+                            .extend(quote!(::StdoutToFile(#file.into_path_buf(), #append, #force))),
+                        2 => redirect
+                            .extend(quote!(::StderrToFile(#file.into_path_buf(), #append, #force))),
+                        _ => abort!(*span, "unsupported fd ({}) redirect to file", fd1),
                     }
                     ret.extend(quote!(.add_redirect(#redirect)));
                 }
-                ParseArg::ArgStr(opt) => {
+                ParseArg::HereDoc(content) => {
+                    ret.extend(quote!(.add_redirect(::cmd_lib::Redirect::HereDoc(
+                        #content.into_os_string()
+                    ))));
+                }
+                ParseArg::CurrentDir(dir) => {
+                    ret.extend(quote!(.with_current_dir(#dir.into_path_buf())));
+                }
+                ParseArg::Condition(cond) => {
+                    self.conditions.push(cond.clone());
+                    ret.extend(quote!(.when(#cond)));
+                }
+                ParseArg::Umask(mode) => {
+                    ret.extend(quote!(.umask(#mode)));
+                }
+                ParseArg::Label(label) => {
+                    ret.extend(quote!(.with_label(#label.into_os_string())));
+                }
+                ParseArg::ArgStr(opt, span) => {
+                    cmd_span.get_or_insert(*span);
                     ret.extend(quote!(.add_arg(#opt)));
                 }
-                ParseArg::ArgVec(opts) => {
+                ParseArg::ArgVec(opts, span) => {
+                    cmd_span.get_or_insert(*span);
                     ret.extend(quote! (.add_args(#opts)));
                 }
                 ParseArg::Pipe | ParseArg::Semicolon => break,
             }
             self.iter.next();
         }
-        ret
+        // `line!()` with the span of user tokens reports the line of the command itself,
+        // instead of the line of the macro invocation
+        let line = quote_spanned!(cmd_span.unwrap_or_else(Span::call_site) => line!());
+        quote!(::cmd_lib::Cmd::default().with_location(file!(), #line) #ret)
     }
 }
@@ -1,6 +1,9 @@
 use crate::{debug, error, info, trace, warn};
 use crate::{CmdEnv, CmdResult};
-use std::io::{Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::str::FromStr;
+use std::time::SystemTime;
 
 pub(crate) fn builtin_echo(env: &mut CmdEnv) -> CmdResult {
     let args = env.get_args();
@@ -13,34 +16,404 @@ pub(crate) fn builtin_echo(env: &mut CmdEnv) -> CmdResult {
     write!(env.stdout(), "{}", msg)
 }
 
+pub(crate) fn builtin_mkdir(env: &mut CmdEnv) -> CmdResult {
+    let mut parents = false;
+    let mut dirs = vec![];
+    for arg in env.get_args() {
+        if arg == "-p" {
+            parents = true;
+        } else if arg.starts_with('-') {
+            return Err(Error::other(format!("mkdir: invalid option {arg:?}")));
+        } else {
+            dirs.push(env.current_dir().join(arg));
+        }
+    }
+    if dirs.is_empty() {
+        return Err(Error::other("mkdir: missing operand"));
+    }
+
+    for dir in dirs {
+        let res = if parents {
+            fs::create_dir_all(&dir)
+        } else {
+            fs::create_dir(&dir)
+        };
+        if let Err(e) = res {
+            // report on stderr like the mkdir program, to be logged along with other commands
+            let msg = format!("mkdir: cannot create directory {:?}", dir.display());
+            writeln!(env.stderr(), "{msg}: {e}")?;
+            return Err(Error::new(e.kind(), msg));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn builtin_tee(env: &mut CmdEnv) -> CmdResult {
+    let mut append = false;
+    let mut files = vec![];
+    for arg in env.get_args() {
+        if arg == "-a" {
+            append = true;
+        } else if arg.starts_with('-') {
+            return Err(Error::other(format!("tee: invalid option {arg:?}")));
+        } else {
+            files.push(env.current_dir().join(arg));
+        }
+    }
+
+    let mut outputs = vec![];
+    for file in files {
+        let f = OpenOptions::new()
+            .create(true)
+            .truncate(!append)
+            .write(true)
+            .append(append)
+            .open(&file)
+            .map_err(|e| Error::new(e.kind(), format!("tee: {:?}: {e}", file.display())))?;
+        outputs.push(f);
+    }
+
+    let mut buf = [0; 8192];
+    loop {
+        let n = match env.stdin().read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        env.stdout().write_all(&buf[..n])?;
+        for output in outputs.iter_mut() {
+            output.write_all(&buf[..n])?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn builtin_touch(env: &mut CmdEnv) -> CmdResult {
+    let mut no_create = false;
+    let mut files = vec![];
+    for arg in env.get_args() {
+        if arg == "-c" {
+            no_create = true;
+        } else if arg.starts_with('-') {
+            return Err(Error::other(format!("touch: invalid option {arg:?}")));
+        } else {
+            files.push(env.current_dir().join(arg));
+        }
+    }
+    if files.is_empty() {
+        return Err(Error::other("touch: missing file operand"));
+    }
+
+    let now = SystemTime::now();
+    for file in files {
+        let created = !file.exists();
+        if created && no_create {
+            continue;
+        }
+        let res = if file.is_dir() {
+            File::open(&file)
+        } else {
+            OpenOptions::new().create(true).append(true).open(&file)
+        };
+        res.and_then(|f| {
+            #[cfg(unix)]
+            if let (true, Some(mode)) = (created, env.umask()) {
+                use std::os::unix::fs::PermissionsExt;
+                f.set_permissions(fs::Permissions::from_mode(0o666 & !mode))?;
+            }
+            f.set_modified(now)
+        })
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("touch: cannot touch {:?}: {e}", file.display()),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+pub(crate) fn builtin_cmp(env: &mut CmdEnv) -> CmdResult {
+    let silent = env.get_args().iter().any(|arg| arg == "-s");
+    let files: Vec<_> = env
+        .get_args()
+        .iter()
+        .filter(|arg| *arg != "-s")
+        .cloned()
+        .collect();
+    let (file1, file2) = match &files[..] {
+        [file1, file2] => (file1.clone(), file2.clone()),
+        [] | [_] => return Err(Error::other("cmp: missing operand")),
+        _ => return Err(Error::other("cmp: too many arguments")),
+    };
+    let read = |file: &str| {
+        fs::read(env.current_dir().join(file))
+            .map_err(|e| Error::new(e.kind(), format!("cmp: {file:?}: {e}")))
+    };
+    let (content1, content2) = (read(&file1)?, read(&file2)?);
+
+    let common = content1.len().min(content2.len());
+    let msg = match content1.iter().zip(&content2).position(|(a, b)| a != b) {
+        Some(offset) => format!("{file1} {file2} differ: byte {}", offset + 1),
+        None if content1.len() > common => format!("EOF on {file2} after byte {common}"),
+        None if content2.len() > common => format!("EOF on {file1} after byte {common}"),
+        None => return Ok(()),
+    };
+    if !silent {
+        writeln!(env.stderr(), "cmp: {msg}")?;
+    }
+    Err(Error::other(format!("cmp: {file1} {file2} differ")))
+}
+
+pub(crate) fn builtin_exit_with(env: &mut CmdEnv) -> CmdResult {
+    let code: i32 = match env.get_args() {
+        [] => return Err(Error::other("exit-with: missing status code")),
+        [code] => code
+            .parse()
+            .map_err(|_| Error::other(format!("exit-with: invalid status code {code:?}")))?,
+        _ => return Err(Error::other("exit-with: too many arguments")),
+    };
+    if code == 0 {
+        return Ok(());
+    }
+    let msg = format!("exit-with: exited with error; status code: {code}");
+    Err(Error::other(ExitWithError { code, msg }))
+}
+
+// error of `exit-with`, keeping the status code for `wait_with_all_and_code()`
+#[derive(Debug)]
+pub(crate) struct ExitWithError {
+    code: i32,
+    msg: String,
+}
+
+impl ExitWithError {
+    pub(crate) fn code_of(e: &Error) -> Option<i32> {
+        e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<ExitWithError>())
+            .map(|exit| exit.code)
+    }
+}
+
+impl std::fmt::Display for ExitWithError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for ExitWithError {}
+
+// replaces the message of `e`, keeping the status code of `exit-with` across nested commands
+pub(crate) fn rewrap_error(e: &Error, msg: String) -> Error {
+    match ExitWithError::code_of(e) {
+        Some(code) => Error::new(e.kind(), ExitWithError { code, msg }),
+        None => Error::new(e.kind(), msg),
+    }
+}
+
 pub(crate) fn builtin_error(env: &mut CmdEnv) -> CmdResult {
-    error!("{}", env.get_args().join(" "));
+    error!("{}", log_message(env)?);
     Ok(())
 }
 
 pub(crate) fn builtin_warn(env: &mut CmdEnv) -> CmdResult {
-    warn!("{}", env.get_args().join(" "));
+    warn!("{}", log_message(env)?);
     Ok(())
 }
 
 pub(crate) fn builtin_info(env: &mut CmdEnv) -> CmdResult {
-    info!("{}", env.get_args().join(" "));
+    info!("{}", log_message(env)?);
     Ok(())
 }
 
 pub(crate) fn builtin_debug(env: &mut CmdEnv) -> CmdResult {
-    debug!("{}", env.get_args().join(" "));
+    debug!("{}", log_message(env)?);
     Ok(())
 }
 
 pub(crate) fn builtin_trace(env: &mut CmdEnv) -> CmdResult {
-    trace!("{}", env.get_args().join(" "));
+    trace!("{}", log_message(env)?);
     Ok(())
 }
 
+// Joins the arguments of logging builtins with spaces, or formats them printf-style if the first
+// one has conversions like "%d" and more arguments follow
+fn log_message(env: &CmdEnv) -> Result<String> {
+    match env.get_args() {
+        [fmt, args @ ..] if !args.is_empty() && has_conversion(fmt) => printf(fmt, args)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {e}", env.get_cmd_name()))),
+        args => Ok(args.join(" ")),
+    }
+}
+
+#[derive(Default)]
+struct Conversion {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: char,
+}
+
+impl Conversion {
+    // parses a conversion after '%', like "-5.2f", returning it with its length
+    fn parse(s: &str) -> Option<(Self, usize)> {
+        let bytes = s.as_bytes();
+        let mut spec = Self::default();
+        let mut i = 0;
+        while let Some(b) = bytes.get(i) {
+            match b {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let digits = |i: &mut usize| {
+            let start = *i;
+            while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+                *i += 1;
+            }
+            s[start..*i].parse().ok()
+        };
+        spec.width = digits(&mut i).unwrap_or(0);
+        if bytes.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(digits(&mut i).unwrap_or(0));
+        }
+        spec.conv = char::from(*bytes.get(i)?);
+        if !"sdiufxXoc".contains(spec.conv) {
+            return None;
+        }
+        Some((spec, i + 1))
+    }
+
+    fn format(&self, arg: &str) -> Result<String> {
+        let signed = |n: String| {
+            if self.plus && !n.starts_with('-') {
+                format!("+{n}")
+            } else {
+                n
+            }
+        };
+        let body = match self.conv {
+            's' => match self.precision {
+                Some(p) => arg.chars().take(p).collect(),
+                None => arg.to_string(),
+            },
+            'c' => arg.chars().take(1).collect(),
+            'f' => {
+                let precision = self.precision.unwrap_or(6);
+                signed(format!("{:.*}", precision, parse_num::<f64>(arg)?))
+            }
+            'x' => format!("{:x}", parse_num::<i64>(arg)?),
+            'X' => format!("{:X}", parse_num::<i64>(arg)?),
+            'o' => format!("{:o}", parse_num::<i64>(arg)?),
+            _ => signed(parse_num::<i64>(arg)?.to_string()),
+        };
+
+        let len = body.chars().count();
+        if len >= self.width {
+            return Ok(body);
+        }
+        let padding = self.width - len;
+        Ok(if self.left {
+            body + &" ".repeat(padding)
+        } else if self.zero && !"sc".contains(self.conv) {
+            let sign_len = if body.starts_with(['-', '+']) { 1 } else { 0 };
+            let (sign, digits) = body.split_at(sign_len);
+            format!("{sign}{}{digits}", "0".repeat(padding))
+        } else {
+            " ".repeat(padding) + &body
+        })
+    }
+}
+
+fn has_conversion(fmt: &str) -> bool {
+    let mut rest = fmt;
+    while let Some(pos) = rest.find('%') {
+        rest = &rest[pos + 1..];
+        if let Some(r) = rest.strip_prefix('%') {
+            rest = r;
+        } else if Conversion::parse(rest).is_some() {
+            return true;
+        }
+    }
+    false
+}
+
+// like printf in bash, but extra arguments are appended with spaces instead of reusing `fmt`
+fn printf(fmt: &str, args: &[String]) -> Result<String> {
+    let mut args = args.iter();
+    let mut out = String::new();
+    let mut rest = fmt;
+    while let Some(pos) = rest.find('%') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        if let Some(r) = rest.strip_prefix('%') {
+            out.push('%');
+            rest = r;
+        } else if let Some((spec, len)) = Conversion::parse(rest) {
+            out.push_str(&spec.format(args.next().map_or("", String::as_str))?);
+            rest = &rest[len..];
+        } else {
+            out.push('%');
+        }
+    }
+    out.push_str(rest);
+    for arg in args {
+        out.push(' ');
+        out.push_str(arg);
+    }
+    Ok(out)
+}
+
+// missing arguments are treated as 0, the same as in bash
+fn parse_num<T: FromStr + Default>(arg: &str) -> Result<T> {
+    if arg.is_empty() {
+        return Ok(T::default());
+    }
+    arg.parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid number {arg:?}")))
+}
+
 pub(crate) fn builtin_empty(env: &mut CmdEnv) -> CmdResult {
     let mut buf = vec![];
     env.stdin().read_to_end(&mut buf)?;
     env.stdout().write_all(&buf)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_printf() {
+        assert!(has_conversion("count: %d"));
+        assert!(!has_conversion("100% done, 50%% left"));
+        assert_eq!(printf("count: %d", &args(&["5"])).unwrap(), "count: 5");
+        assert_eq!(
+            printf("[%-4s|%5s|%.2s]", &args(&["ab", "cd", "xyz"])).unwrap(),
+            "[ab  |   cd|xy]"
+        );
+        assert_eq!(
+            printf(
+                "%05d %+d %.2f %x %o %c%%",
+                &args(&["-42", "7", "3.14159", "255", "8", "yes"])
+            )
+            .unwrap(),
+            "-0042 +7 3.14 ff 10 y%"
+        );
+        assert_eq!(printf("%s=%d", &args(&["a"])).unwrap(), "a=0");
+        assert_eq!(printf("%s", &args(&["a", "b", "c"])).unwrap(), "a b c");
+        assert!(printf("%d", &args(&["five"])).is_err());
+    }
+}
@@ -1,9 +1,26 @@
+use crate::builtins::{rewrap_error, ExitWithError};
+use crate::process::{self, output_trim, TrimMode};
 use crate::{info, warn};
-use crate::{process, CmdResult, FunResult};
-use os_pipe::PipeReader;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
+use crate::{CmdResult, FunResult};
+use os_pipe::{PipeReader, PipeWriter};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, RawHandle};
 use std::process::{Child, ExitStatus};
-use std::thread::JoinHandle;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const STDERR_TRUNCATED: &str = "...(stderr truncated)";
+#[cfg(target_os = "linux")]
+const STDOUT_WATCHDOG_DELAY: Duration = Duration::from_secs(3);
+
+// predicate on stderr lines, and the sender to report the first matching one
+type StderrStop = (Arc<dyn Fn(&str) -> bool + Send + Sync>, Sender<String>);
 
 /// Representation of running or exited children processes, connected with pipes
 /// optionally.
@@ -24,12 +41,18 @@ impl CmdChildren {
 
     pub(crate) fn into_fun_children(self) -> FunChildren {
         FunChildren {
+            watchdog: StdoutWatchdog::default(),
             children: self.children,
             ignore_error: self.ignore_error,
+            stdin: None,
         }
     }
 
     /// Waits for the children processes to exit completely, returning the status that they exited with.
+    ///
+    /// If stdout was captured by spawning with `with_output` set, it is not read here, and the
+    /// children can block forever on a full pipe. Use
+    /// [`wait_discarding_output()`](Self::wait_discarding_output) instead in that case.
     pub fn wait(&mut self) -> CmdResult {
         // wait for the last child result
         let handle = self.children.pop().unwrap();
@@ -40,6 +63,70 @@ impl CmdChildren {
         Self::wait_children(&mut self.children)
     }
 
+    /// Waits for the children processes to exit completely like [`wait()`](Self::wait), reading
+    /// and discarding their captured stdout meanwhile, so they never block on a full pipe.
+    pub fn wait_discarding_output(&mut self) -> CmdResult {
+        let drained = match self.children.last_mut() {
+            Some(child) => child.drain_stdout(),
+            None => Ok(()),
+        };
+        self.wait().and(drained)
+    }
+
+    /// Waits for the children processes to exit completely like [`wait()`](Self::wait), but no
+    /// later than `deadline`, which can be shared by several pipelines.
+    ///
+    /// If the deadline is reached, the children processes are killed, and an error with
+    /// [`ErrorKind::TimedOut`] is returned.
+    pub fn wait_deadline(&mut self, deadline: Instant) -> CmdResult {
+        self.wait_deadline_with(deadline, "missed its deadline")
+    }
+
+    // waits like `wait_deadline()`, with `timed_out` in the error once the deadline is reached
+    pub(crate) fn wait_deadline_with(&mut self, deadline: Instant, timed_out: &str) -> CmdResult {
+        let mut stderr_threads: Vec<StderrThread> = self
+            .children
+            .iter_mut()
+            .map(|child| child.stderr_thread(false))
+            .collect();
+        if let Err(e) = Self::wait_until(&mut self.children, deadline, timed_out) {
+            stderr_threads.iter_mut().for_each(StderrThread::detach);
+            return Err(e);
+        }
+        self.wait()
+    }
+
+    // polls the children until they all exit, or kills them once the deadline is reached
+    fn wait_until(children: &mut Vec<CmdChild>, deadline: Instant, timed_out: &str) -> CmdResult {
+        loop {
+            let mut all_exited = true;
+            for child in children.iter_mut() {
+                if !child.handle.try_wait()? {
+                    all_exited = false;
+                    break;
+                }
+            }
+            if all_exited {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let last = children.last().unwrap();
+                let err = Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "Running [{}] {timed_out} at {}:{}",
+                        last.cmd, last.file, last.line
+                    ),
+                );
+                while let Some(child) = children.pop() {
+                    let _ = child.kill();
+                }
+                return Err(err);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
     fn wait_children(children: &mut Vec<CmdChild>) -> CmdResult {
         let mut ret = Ok(());
         while let Some(child_handle) = children.pop() {
@@ -52,8 +139,12 @@ impl CmdChildren {
 
     /// Forces the children processes to exit.
     pub fn kill(&mut self) -> CmdResult {
+        Self::kill_children(&mut self.children)
+    }
+
+    fn kill_children(children: &mut Vec<CmdChild>) -> CmdResult {
         let mut ret = Ok(());
-        while let Some(child_handle) = self.children.pop() {
+        while let Some(child_handle) = children.pop() {
             if let Err(e) = child_handle.kill() {
                 ret = Err(e);
             }
@@ -61,6 +152,34 @@ impl CmdChildren {
         ret
     }
 
+    /// Returns whether all the children processes have exited, without waiting for them or
+    /// consuming them, to poll their status repeatedly before a final [`wait()`](Self::wait).
+    pub fn all_finished(&mut self) -> bool {
+        // a status which can not be polled is reported by `wait()`
+        self.children
+            .iter_mut()
+            .all(|child| child.handle.try_wait().unwrap_or(true))
+    }
+
+    /// Returns the error of the first child process which already failed, like the one
+    /// [`wait()`](Self::wait) would return, without waiting for the others or consuming them.
+    ///
+    /// Like for [`wait()`](Self::wait), failures before the last command only count with
+    /// pipefail enabled.
+    pub fn any_failed(&mut self) -> Option<Error> {
+        let total = self.children.len();
+        let pipefail = process::pipefail_enabled();
+        self.children
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| pipefail || i + 1 == total)
+            .find_map(|(_, child)| {
+                child
+                    .handle
+                    .try_wait_error(&child.cmd, &child.file, child.line)
+            })
+    }
+
     /// Returns the OS-assigned process identifiers associated with these children processes
     pub fn pids(&self) -> Vec<u32> {
         self.children.iter().filter_map(|x| x.pid()).collect()
@@ -72,33 +191,389 @@ impl CmdChildren {
 ///
 /// Calling [spawn_with_output!](../cmd_lib/macro.spawn_with_output.html) macro will return `Result<FunChildren>`
 pub struct FunChildren {
+    // declared first, to be stopped before the stdout pipe is closed when dropped
+    watchdog: StdoutWatchdog,
     children: Vec<CmdChild>,
     ignore_error: bool,
+    // stdin of the first command, if spawned with a pipe, closed once waiting unless taken
+    stdin: Option<PipeWriter>,
 }
 
 impl FunChildren {
+    pub(crate) fn with_stdin(mut self, stdin: PipeWriter) -> Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    // in debug mode, warns if the stdout pipe is still full a while after spawning, and nothing
+    // has started waiting for the children, since they then block until it is read, which
+    // looks like a hang to new users
+    pub(crate) fn with_stdout_watchdog(mut self) -> Self {
+        #[cfg(target_os = "linux")]
+        if let Some(child) = self.children.last() {
+            if let Some(fd) = child.stdout.as_ref().map(PipeReader::as_raw_fd) {
+                let cmd = child.cmd.clone();
+                let stopped = Arc::new(Mutex::new(false));
+                self.watchdog = StdoutWatchdog(Some(stopped.clone()));
+                let _ = thread::Builder::new().spawn(move || {
+                    thread::sleep(STDOUT_WATCHDOG_DELAY);
+                    // the pipe is not closed while the lock is held, without being stopped first
+                    let stopped = stopped.lock().unwrap();
+                    if !*stopped && pipe_is_full(fd) {
+                        warn!(
+                            "Running [{cmd}] is blocked on its full stdout pipe, not read for \
+                            {STDOUT_WATCHDOG_DELAY:?}, read it with wait_with_output() or similar"
+                        );
+                    }
+                });
+            }
+        }
+        self
+    }
+
+    /// Takes the writer to the stdin of the first command, if spawned with
+    /// [`spawn_with_stdin!`](../cmd_lib/macro.spawn_with_stdin.html), to drive interactive
+    /// programs along with reading their output.
+    ///
+    /// Drop the writer to close stdin, since most programs only exit at the end of their input.
+    /// If it is not taken, stdin is closed when waiting for the children processes.
+    pub fn take_stdin(&mut self) -> Option<Box<dyn Write + Send>> {
+        self.stdin
+            .take()
+            .map(|stdin| Box::new(stdin) as Box<dyn Write + Send>)
+    }
+
+    /// Returns the raw file descriptor of the stdout pipe of the last command, to hand it over to
+    /// processes spawned without cmd_lib, as a lower-level alternative to
+    /// [`pipe_into()`](Self::pipe_into).
+    ///
+    /// The descriptor is still owned by these children, so duplicate it to keep it beyond them.
+    /// Whatever is not read through it is still returned by the `wait_with_*` methods.
+    #[cfg(unix)]
+    pub fn stdout_fd(&self) -> Option<RawFd> {
+        self.children
+            .last()
+            .and_then(|child| child.stdout.as_ref())
+            .map(|stdout| stdout.as_raw_fd())
+    }
+
+    /// Returns the raw handle of the stdout pipe of the last command, like
+    /// [`stdout_fd()`](Self::stdout_fd) on unix.
+    #[cfg(windows)]
+    pub fn stdout_handle(&self) -> Option<RawHandle> {
+        self.children
+            .last()
+            .and_then(|child| child.stdout.as_ref())
+            .map(|stdout| stdout.as_raw_handle())
+    }
+
     /// Waits for the children processes to exit completely, returning the command result, stdout
     /// content string and stderr content string.
+    ///
+    /// Stdout and stderr are read from separate pipes, so the relative order of their lines is
+    /// not preserved. If the console transcript order matters, merge stderr into stdout with
+    /// `2>&1` when spawning, and use [`wait_with_output()`](Self::wait_with_output) instead.
     pub fn wait_with_all(&mut self) -> (CmdResult, String, String) {
         self.inner_wait_with_all(true)
     }
 
+    /// Waits for the children processes to exit completely like
+    /// [`wait_with_all()`](Self::wait_with_all), also returning the exit code of the last command,
+    /// e.g. for a custom command to tell apart the failures of the tool it runs.
+    ///
+    /// Builtin and custom commands exit with 0 on success and 1 on failure, unless the code is
+    /// set by `exit-with`. The code is `None` if the process was terminated by a signal.
+    pub fn wait_with_all_and_code(&mut self) -> (CmdResult, Option<i32>, String, String) {
+        self.inner_wait_with_all_and_code(true)
+    }
+
+    /// Waits for the children processes to exit completely like
+    /// [`wait_with_all()`](Self::wait_with_all), but streams stdout into `stdout` instead of
+    /// buffering it in memory, returning the command result and stderr content string.
+    ///
+    /// Stderr is only captured if `capture_stderr` is set, otherwise it is logged as usual.
+    pub fn wait_with_all_to(
+        &mut self,
+        stdout: &mut dyn Write,
+        capture_stderr: bool,
+    ) -> (CmdResult, String) {
+        self.start_waiting();
+        let handle = self.children.pop().unwrap();
+        let mut stderr = String::new();
+        let (res, _) = handle.wait_with_all(capture_stderr, stdout, &mut stderr);
+        let _ = CmdChildren::wait_children(&mut self.children);
+        (res, stderr)
+    }
+
     /// Waits for the children processes to exit completely, returning the stdout output.
     pub fn wait_with_output(&mut self) -> FunResult {
+        let mut stdout = self.wait_with_untrimmed_output()?;
+        output_trim().apply(&mut stdout);
+        Ok(stdout)
+    }
+
+    /// Waits for the children processes to exit completely, returning the stdout output trimmed
+    /// with `mode` instead of the global [`set_output_trim()`](crate::set_output_trim) setting.
+    pub fn wait_with_output_trimmed(&mut self, mode: TrimMode) -> FunResult {
+        let mut stdout = self.wait_with_untrimmed_output()?;
+        mode.apply(&mut stdout);
+        Ok(stdout)
+    }
+
+    /// Waits for the children processes to exit completely, returning the stdout output as is,
+    /// without trimming the trailing newline.
+    pub fn wait_with_untrimmed_output(&mut self) -> FunResult {
+        let (res, _, stdout, _) = self.inner_wait_with_all_untrimmed(false);
+        if let Err(e) = res {
+            if !self.ignore_error {
+                return Err(e);
+            }
+        }
+        Ok(stdout)
+    }
+
+    /// Waits for the children processes to exit completely, returning the command result and the
+    /// stdout output, which is kept even if the command fails.
+    pub fn wait_with_output_and_status(&mut self) -> (CmdResult, String) {
         let (res, stdout, _) = self.inner_wait_with_all(false);
+        if self.ignore_error {
+            return (Ok(()), stdout);
+        }
+        (res, stdout)
+    }
+
+    /// Waits for the children processes to exit completely, echoing the stdout output to the
+    /// parent's stdout while it is produced, and returning it as well, like `tee`.
+    pub fn wait_with_tee(&mut self) -> FunResult {
+        self.start_waiting();
+        let stderr_thread = self
+            .children
+            .last_mut()
+            .map(|child| child.stderr_thread(false));
+        let mut stdout_buf = Vec::new();
+        let mut tee_res = Ok(());
+        if let Some(mut stdout) = self.take_stdout() {
+            let mut buf = vec![0; process::pipe_buffer_size()];
+            let mut console = std::io::stdout();
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        stdout_buf.extend_from_slice(&buf[..n]);
+                        if let Err(e) = console.write_all(&buf[..n]).and_then(|_| console.flush()) {
+                            tee_res = Err(e);
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        tee_res = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+        drop(stderr_thread);
+        self.wait_taken().and(tee_res)?;
+        let mut stdout: String = String::from_utf8_lossy(&stdout_buf).into();
+        output_trim().apply(&mut stdout);
+        Ok(stdout)
+    }
+
+    /// Waits for the children processes to exit completely within `timeout`, returning the stdout
+    /// output.
+    ///
+    /// If the timeout expires, the children processes are killed, and an error with
+    /// [`ErrorKind::TimedOut`] is returned.
+    pub fn wait_with_output_timeout(&mut self, timeout: Duration) -> FunResult {
+        let deadline = Instant::now() + timeout;
+        self.wait_with_output_until(deadline, &format!("timed out after {timeout:?}"), true)
+    }
+
+    // waits like `wait_with_output_timeout()`, with `timed_out` in the error once the deadline is
+    // reached, and only trimming the trailing newline with `trim`
+    pub(crate) fn wait_with_output_until(
+        &mut self,
+        deadline: Instant,
+        timed_out: &str,
+        trim: bool,
+    ) -> FunResult {
+        self.start_waiting();
+        // drain stdout and stderr while polling, so the children won't block on full pipes
+        let stdout_thread = self
+            .children
+            .last_mut()
+            .and_then(|child| child.stdout.take())
+            .map(|mut stdout| {
+                thread::spawn(move || {
+                    let mut buf = vec![];
+                    stdout.read_to_end(&mut buf).map(|_| buf)
+                })
+            });
+        let mut stderr_threads: Vec<StderrThread> = self
+            .children
+            .iter_mut()
+            .map(|child| child.stderr_thread(false))
+            .collect();
+
+        if let Err(e) = CmdChildren::wait_until(&mut self.children, deadline, timed_out) {
+            // the stdout thread is detached by dropping its handle
+            stderr_threads.iter_mut().for_each(StderrThread::detach);
+            return Err(e);
+        }
+
+        let mut stdout_buf = vec![];
+        if let Some(stdout_thread) = stdout_thread {
+            match stdout_thread.join() {
+                Ok(res) => stdout_buf = res?,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Running stdout thread joined with error: {e:?}"
+                    )))
+                }
+            }
+        }
+        let (res, _, _) = self.inner_wait_with_all(false);
         if let Err(e) = res {
             if !self.ignore_error {
                 return Err(e);
             }
         }
+        let mut stdout: String = String::from_utf8_lossy(&stdout_buf).into();
+        if trim {
+            output_trim().apply(&mut stdout);
+        }
+        Ok(stdout)
+    }
+
+    /// Waits for the children processes to exit completely, returning the stdout output, but
+    /// reading at most `max` bytes of it, to guard against runaway commands.
+    ///
+    /// If the output exceeds `max` bytes, the children processes are killed, and an error telling
+    /// the output was truncated is returned.
+    pub fn wait_with_output_limited(&mut self, max: usize) -> FunResult {
+        self.start_waiting();
+        let stderr_thread = self
+            .children
+            .last_mut()
+            .map(|child| child.stderr_thread(false));
+        let mut stdout_buf = Vec::new();
+        if let Some(stdout) = self.take_stdout() {
+            // read one more byte to tell whether there is more output than `max`
+            let limit = (max as u64).saturating_add(1);
+            if let Err(e) = stdout.take(limit).read_to_end(&mut stdout_buf) {
+                let _ = self.kill();
+                drop(stderr_thread);
+                return Err(e);
+            }
+        }
+        if stdout_buf.len() > max {
+            let last = self.children.last().unwrap();
+            let err = Error::other(format!(
+                "Running [{}] output truncated after {max} bytes at {}:{}",
+                last.cmd, last.file, last.line
+            ));
+            let _ = self.kill();
+            drop(stderr_thread);
+            return Err(err);
+        }
+        drop(stderr_thread);
+        self.wait_taken()?;
+        let mut stdout: String = String::from_utf8_lossy(&stdout_buf).into();
+        output_trim().apply(&mut stdout);
+        Ok(stdout)
+    }
+
+    /// Waits for the children processes to exit completely, returning the stdout output, but
+    /// stops them as soon as any of them writes a stderr line matching `matches`, e.g. to abort a
+    /// long build on its first "error:" line.
+    ///
+    /// Stderr lines are still logged as usual. On a match, the children processes are killed,
+    /// and an error with the matching line is returned.
+    pub fn wait_until_stderr_matches<F>(&mut self, matches: F) -> FunResult
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.start_waiting();
+        let (tx, rx) = mpsc::channel();
+        let matches: Arc<dyn Fn(&str) -> bool + Send + Sync> = Arc::new(matches);
+        let mut stderr_threads: Vec<StderrThread> = self
+            .children
+            .iter_mut()
+            .map(|child| child.stderr_thread_until(false, Some((matches.clone(), tx.clone()))))
+            .collect();
+        drop(tx);
+        // read stdout on its own thread, so a match can be reported while the output is pending
+        let stdout_thread = self.take_stdout().map(|mut stdout| {
+            thread::spawn(move || {
+                let mut buf = vec![];
+                stdout.read_to_end(&mut buf).map(|_| buf)
+            })
+        });
+
+        // all senders are dropped once the stderr of every child is closed, without any match
+        if let Ok(line) = rx.recv() {
+            let last = self.children.last().unwrap();
+            let err = Error::other(format!(
+                "Running [{}] stopped on stderr line {line:?} at {}:{}",
+                last.cmd, last.file, last.line
+            ));
+            let _ = self.kill();
+            stderr_threads.iter_mut().for_each(StderrThread::detach);
+            return Err(err);
+        }
+        let mut stdout_buf = vec![];
+        if let Some(stdout_thread) = stdout_thread {
+            match stdout_thread.join() {
+                Ok(res) => stdout_buf = res?,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Running stdout thread joined with error: {e:?}"
+                    )))
+                }
+            }
+        }
+        self.wait_taken()?;
+        let mut stdout: String = String::from_utf8_lossy(&stdout_buf).into();
+        output_trim().apply(&mut stdout);
         Ok(stdout)
     }
 
+    /// Waits like [`wait_until_stderr_matches()`](Self::wait_until_stderr_matches), stopping the
+    /// children processes on the first stderr line matching the regular expression `re`.
+    #[cfg(feature = "regex")]
+    pub fn wait_until_stderr_matches_regex(&mut self, re: regex::Regex) -> FunResult {
+        self.wait_until_stderr_matches(move |line| re.is_match(line))
+    }
+
     /// Waits for the children processes to exit completely, and read all bytes from stdout into `buf`.
     pub fn wait_with_raw_output(&mut self, buf: &mut Vec<u8>) -> CmdResult {
+        let mut _stderr = String::new();
+        self.inner_wait_with_raw_output(false, buf, &mut _stderr)
+    }
+
+    /// Waits for the children processes to exit completely like
+    /// [`wait_with_raw_output()`](Self::wait_with_raw_output), and captures stderr into `stderr`
+    /// instead of logging it, to keep the diagnostics of binary output tools.
+    pub fn wait_with_raw_output_and_stderr(
+        &mut self,
+        stdout: &mut Vec<u8>,
+        stderr: &mut String,
+    ) -> CmdResult {
+        self.inner_wait_with_raw_output(true, stdout, stderr)
+    }
+
+    fn inner_wait_with_raw_output(
+        &mut self,
+        capture_stderr: bool,
+        stdout: &mut Vec<u8>,
+        stderr: &mut String,
+    ) -> CmdResult {
+        self.start_waiting();
         // wait for the last child result
         let handle = self.children.pop().unwrap();
-        let wait_last = handle.wait_with_raw_output(self.ignore_error, buf);
+        let wait_last =
+            handle.wait_with_raw_output(self.ignore_error, capture_stderr, stdout, stderr);
         match wait_last {
             Err(e) => {
                 let _ = CmdChildren::wait_children(&mut self.children);
@@ -118,29 +593,72 @@ impl FunChildren {
     /// Waits for the children processes to exit completely, pipe content will be processed by
     /// provided function.
     pub fn wait_with_pipe(&mut self, f: &mut dyn FnMut(Box<dyn Read>)) -> CmdResult {
+        self.start_waiting();
+        let mut child = self.children.pop().unwrap();
+        let stderr_thread = child.stderr_thread(false);
+        Self::pipe_stdout(child.handle, child.stdout, f);
+        drop(stderr_thread);
+        CmdChildren::wait_children(&mut self.children)
+    }
+
+    /// Waits for the children processes to exit completely, like
+    /// [`wait_with_pipe()`](Self::wait_with_pipe), but also calls `stderr_f` for each stderr line
+    /// of the last command as it is written, instead of logging it, e.g. to parse progress
+    /// reported on stderr.
+    ///
+    /// `stderr_f` runs on its own thread, concurrently with `stdout_f`.
+    pub fn wait_with_pipes(
+        &mut self,
+        stdout_f: &mut dyn FnMut(Box<dyn Read>),
+        stderr_f: &mut (dyn FnMut(String) + Send),
+    ) -> CmdResult {
+        self.start_waiting();
         let child = self.children.pop().unwrap();
-        let stderr_thread =
-            StderrThread::new(&child.cmd, &child.file, child.line, child.stderr, false);
-        match child.handle {
+        thread::scope(|s| {
+            if let Some(stderr) = child.stderr {
+                s.spawn(move || {
+                    BufReader::new(stderr)
+                        .lines()
+                        .map_while(Result::ok)
+                        .for_each(stderr_f)
+                });
+            }
+            Self::pipe_stdout(child.handle, child.stdout, stdout_f);
+        });
+        CmdChildren::wait_children(&mut self.children)
+    }
+
+    fn pipe_stdout(
+        handle: CmdChildHandle,
+        stdout: Option<PipeReader>,
+        f: &mut dyn FnMut(Box<dyn Read>),
+    ) {
+        let buffer_size = process::pipe_buffer_size();
+        match handle {
             CmdChildHandle::Proc(mut proc) => {
-                if let Some(stdout) = child.stdout {
-                    f(Box::new(stdout));
+                if let Some(stdout) = stdout {
+                    f(Box::new(BufReader::with_capacity(buffer_size, stdout)));
                     let _ = proc.kill();
                 }
             }
-            CmdChildHandle::Thread(_) => {
-                if let Some(stdout) = child.stdout {
-                    f(Box::new(stdout));
+            CmdChildHandle::Thread(_) | CmdChildHandle::Joined(_) => {
+                if let Some(stdout) = stdout {
+                    f(Box::new(BufReader::with_capacity(buffer_size, stdout)));
                 }
             }
             CmdChildHandle::SyncFn => {
-                if let Some(stdout) = child.stdout {
-                    f(Box::new(stdout));
+                if let Some(stdout) = stdout {
+                    f(Box::new(BufReader::with_capacity(buffer_size, stdout)));
                 }
             }
         };
-        drop(stderr_thread);
-        CmdChildren::wait_children(&mut self.children)
+    }
+
+    /// Forces the children processes to exit, e.g. to terminate the pipeline after reading only
+    /// part of its output.
+    pub fn kill(&mut self) -> CmdResult {
+        self.start_waiting();
+        CmdChildren::kill_children(&mut self.children)
     }
 
     /// Returns the OS-assigned process identifiers associated with these children processes.
@@ -148,18 +666,192 @@ impl FunChildren {
         self.children.iter().filter_map(|x| x.pid()).collect()
     }
 
+    /// Pipes the stdout of these children processes into the stdin of `next`, which must be
+    /// spawned with [`spawn_with_stdin!`](../cmd_lib/macro.spawn_with_stdin.html), returning the
+    /// combined children processes, with output from `next`.
+    ///
+    /// The stdout is copied by a thread, which is waited like the other children processes, so a
+    /// failed copy is reported like a failed command in the middle of a pipe. Errors are ignored
+    /// only if these children processes were spawned with `ignore`.
+    /// ```no_run
+    /// # use cmd_lib::*;
+    /// let mut proc = spawn_with_output!(cat data.csv)?;
+    /// if std::env::args().any(|arg| arg == "--sorted") {
+    ///     proc = proc.pipe_into(spawn_with_stdin!(sort)?)?;
+    /// }
+    /// let output = proc.wait_with_output()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn pipe_into(mut self, mut next: FunChildren) -> Result<FunChildren> {
+        let (last, first) = match (self.children.last(), next.children.first()) {
+            (Some(last), Some(first)) => (last, first),
+            _ => return Ok(self),
+        };
+        let cmd = format!("{} | {}", last.cmd, first.cmd);
+        let (file, line) = (last.file.clone(), last.line);
+        let mut stdin = match next.stdin.take() {
+            Some(stdin) => stdin,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Piping [{cmd}] failed: next commands not spawned with spawn_with_stdin! \
+                        at {file}:{line}"
+                    ),
+                ))
+            }
+        };
+        let handle = match self.take_stdout() {
+            Some(mut stdout) => thread::Builder::new().spawn(move || {
+                std::io::copy(&mut stdout, &mut stdin)?;
+                Ok(())
+            })?,
+            None => thread::Builder::new().spawn(|| Ok(()))?,
+        };
+        let copy = CmdChild::new(CmdChildHandle::Thread(handle), cmd, file, line, None, None);
+        self.children.push(copy);
+        self.children.append(&mut next.children);
+        Ok(self)
+    }
+
+    /// Returns an iterator over the stdout of these children processes in chunks of `size` bytes,
+    /// for binary data. The last chunk may be shorter.
+    ///
+    /// The children processes are waited once the iterator is exhausted or dropped, and their
+    /// error, if any, is returned as the last item.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn chunks(mut self, size: usize) -> impl Iterator<Item = Result<Vec<u8>>> {
+        assert!(size != 0, "chunk size must be non-zero");
+        self.start_waiting();
+        let stdout = self.take_stdout();
+        let stderr_thread = self
+            .children
+            .last_mut()
+            .map(|child| child.stderr_thread(false));
+        OutputChunks {
+            children: Some(self),
+            stdout,
+            stderr_thread,
+            size,
+        }
+    }
+
+    // closes stdin, and stops the stdout watchdog, once the children are waited or killed
+    fn start_waiting(&mut self) {
+        self.stdin.take();
+        self.watchdog.stop();
+    }
+
+    fn take_stdout(&mut self) -> Option<PipeReader> {
+        self.watchdog.stop();
+        self.children
+            .last_mut()
+            .and_then(|child| child.stdout.take())
+    }
+
+    // wait for the children processes, after their stdout was taken
+    fn wait_taken(&mut self) -> CmdResult {
+        let handle = self.children.pop().unwrap();
+        let wait_last = handle.wait(true);
+        let ret = CmdChildren::wait_children(&mut self.children);
+        if self.ignore_error {
+            return Ok(());
+        }
+        wait_last.and(ret)
+    }
+
     fn inner_wait_with_all(&mut self, capture_stderr: bool) -> (CmdResult, String, String) {
+        let (res, _, stdout, stderr) = self.inner_wait_with_all_and_code(capture_stderr);
+        (res, stdout, stderr)
+    }
+
+    fn inner_wait_with_all_and_code(
+        &mut self,
+        capture_stderr: bool,
+    ) -> (CmdResult, Option<i32>, String, String) {
+        let (res, code, mut stdout, stderr) = self.inner_wait_with_all_untrimmed(capture_stderr);
+        output_trim().apply(&mut stdout);
+        (res, code, stdout, stderr)
+    }
+
+    fn inner_wait_with_all_untrimmed(
+        &mut self,
+        capture_stderr: bool,
+    ) -> (CmdResult, Option<i32>, String, String) {
+        self.start_waiting();
         // wait for the last child result
         let handle = self.children.pop().unwrap();
         let mut stdout_buf = Vec::new();
         let mut stderr = String::new();
-        let res = handle.wait_with_all(capture_stderr, &mut stdout_buf, &mut stderr);
+        let (res, code) = handle.wait_with_all(capture_stderr, &mut stdout_buf, &mut stderr);
         let _ = CmdChildren::wait_children(&mut self.children);
-        let mut stdout: String = String::from_utf8_lossy(&stdout_buf).into();
-        if stdout.ends_with('\n') {
-            stdout.pop();
+        let stdout = String::from_utf8_lossy(&stdout_buf).into();
+        (res, code, stdout, stderr)
+    }
+}
+
+// stops the stdout watchdog thread, if any, before the stdout pipe is read or closed
+#[derive(Default)]
+struct StdoutWatchdog(Option<Arc<Mutex<bool>>>);
+
+impl StdoutWatchdog {
+    fn stop(&mut self) {
+        if let Some(stopped) = self.0.take() {
+            *stopped.lock().unwrap() = true;
+        }
+    }
+}
+
+impl Drop for StdoutWatchdog {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct OutputChunks {
+    children: Option<FunChildren>,
+    stdout: Option<PipeReader>,
+    stderr_thread: Option<StderrThread>,
+    size: usize,
+}
+
+impl OutputChunks {
+    fn finish(&mut self) -> CmdResult {
+        // close stdout at first, so that early dropping does not block the writers forever
+        self.stdout = None;
+        self.stderr_thread = None;
+        match self.children.take() {
+            Some(mut children) => children.wait_taken(),
+            None => Ok(()),
         }
-        (res, stdout, stderr)
+    }
+}
+
+impl Iterator for OutputChunks {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(stdout) = self.stdout.as_mut() {
+            let mut chunk = Vec::with_capacity(self.size);
+            match stdout.take(self.size as u64).read_to_end(&mut chunk) {
+                Ok(_) if !chunk.is_empty() => return Some(Ok(chunk)),
+                Ok(_) => {}
+                Err(e) => {
+                    self.stdout = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.finish().err().map(Err)
+    }
+}
+
+impl Drop for OutputChunks {
+    fn drop(&mut self) {
+        let _ = self.finish();
     }
 }
 
@@ -170,6 +862,8 @@ pub(crate) struct CmdChild {
     line: u32,
     stdout: Option<PipeReader>,
     stderr: Option<PipeReader>,
+    label: Option<String>,
+    stdout_thread: Option<JoinHandle<()>>,
 }
 
 impl CmdChild {
@@ -188,24 +882,69 @@ impl CmdChild {
             cmd,
             stdout,
             stderr,
+            label: None,
+            stdout_thread: None,
+        }
+    }
+
+    // prefixes the logged stderr lines with `label`, and with `forward_stdout`, also the stdout
+    // lines, which are forwarded to the parent's stdout instead of being inherited
+    pub(crate) fn with_label(mut self, label: Option<String>, forward_stdout: bool) -> Self {
+        if let (Some(label), true) = (label.clone(), forward_stdout) {
+            if let Some(stdout) = self.stdout.take() {
+                self.stdout_thread = Some(std::thread::spawn(move || {
+                    let mut console = std::io::stdout();
+                    BufReader::new(stdout)
+                        .lines()
+                        .map_while(Result::ok)
+                        .for_each(|line| {
+                            let _ = writeln!(console, "[{label}] {line}");
+                        });
+                }));
+            }
         }
+        self.label = label;
+        self
+    }
+
+    fn stderr_thread(&mut self, capture: bool) -> StderrThread {
+        self.stderr_thread_until(capture, None)
+    }
+
+    fn stderr_thread_until(&mut self, capture: bool, stop: Option<StderrStop>) -> StderrThread {
+        StderrThread::new(
+            &self.cmd,
+            &self.file,
+            self.line,
+            self.stderr.take(),
+            self.label.clone(),
+            capture,
+            stop,
+        )
     }
 
     fn wait(mut self, is_last: bool) -> CmdResult {
-        let _stderr_thread =
-            StderrThread::new(&self.cmd, &self.file, self.line, self.stderr.take(), false);
+        let mut stderr_thread = self.stderr_thread(false);
         let res = self.handle.wait(&self.cmd, &self.file, self.line);
+        if let Some(stdout_thread) = self.stdout_thread.take() {
+            let _ = stdout_thread.join();
+        }
         if let Err(e) = res {
             if is_last || process::pipefail_enabled() {
-                return Err(e);
+                return Err(with_stderr_tail(e, stderr_thread.join()));
             }
         }
         Ok(())
     }
 
-    fn wait_with_raw_output(self, ignore_error: bool, stdout_buf: &mut Vec<u8>) -> CmdResult {
-        let mut _stderr = String::new();
-        let res = self.wait_with_all(false, stdout_buf, &mut _stderr);
+    fn wait_with_raw_output(
+        self,
+        ignore_error: bool,
+        capture_stderr: bool,
+        stdout_buf: &mut Vec<u8>,
+        stderr_buf: &mut String,
+    ) -> CmdResult {
+        let (res, _) = self.wait_with_all(capture_stderr, stdout_buf, stderr_buf);
         if ignore_error {
             return Ok(());
         }
@@ -215,25 +954,40 @@ impl CmdChild {
     fn wait_with_all(
         mut self,
         capture_stderr: bool,
-        stdout_buf: &mut Vec<u8>,
+        stdout_buf: &mut dyn Write,
         stderr_buf: &mut String,
-    ) -> CmdResult {
-        let mut stderr_thread = StderrThread::new(
-            &self.cmd,
-            &self.file,
-            self.line,
-            self.stderr.take(),
-            capture_stderr,
-        );
+    ) -> (CmdResult, Option<i32>) {
+        let mut stderr_thread = self.stderr_thread(capture_stderr);
         let mut stdout_res = Ok(());
         if let Some(mut stdout) = self.stdout.take() {
-            if let Err(e) = stdout.read_to_end(stdout_buf) {
+            if let Err(e) = std::io::copy(&mut stdout, stdout_buf) {
                 stdout_res = Err(e)
             }
         }
-        *stderr_buf = stderr_thread.join();
-        let wait_res = self.handle.wait(&self.cmd, &self.file, self.line);
-        wait_res.and(stdout_res)
+        let stderr = stderr_thread.join();
+        let (mut wait_res, code) = self.handle.wait_with_code(&self.cmd, &self.file, self.line);
+        if capture_stderr {
+            *stderr_buf = stderr;
+        } else {
+            wait_res = wait_res.map_err(|e| with_stderr_tail(e, stderr));
+        }
+        (wait_res.and(stdout_res), code)
+    }
+
+    // read the remaining stdout until the writers exit, to keep them from blocking on the pipe
+    fn drain_stdout(&mut self) -> CmdResult {
+        if let Some(mut stdout) = self.stdout.take() {
+            let mut buf = vec![0; process::pipe_buffer_size()];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
     }
 
     fn kill(self) -> CmdResult {
@@ -248,78 +1002,128 @@ impl CmdChild {
 pub(crate) enum CmdChildHandle {
     Proc(Child),
     Thread(JoinHandle<CmdResult>),
+    // a thread which was joined while polling, keeping its result for `wait()`
+    Joined(thread::Result<CmdResult>),
     SyncFn,
 }
 
 impl CmdChildHandle {
     fn wait(self, cmd: &str, file: &str, line: u32) -> CmdResult {
+        self.wait_with_code(cmd, file, line).0
+    }
+
+    // also returns the exit code, which is 0 or 1 for builtin and custom commands, unless set by
+    // `exit-with`, and none if the process was terminated by a signal or could not be waited
+    fn wait_with_code(self, cmd: &str, file: &str, line: u32) -> (CmdResult, Option<i32>) {
         match self {
             CmdChildHandle::Proc(mut proc) => {
                 let status = proc.wait();
                 match status {
-                    Err(e) => return Err(process::new_cmd_io_error(&e, cmd, file, line)),
+                    Err(e) => (Err(process::new_cmd_io_error(&e, cmd, file, line)), None),
                     Ok(status) => {
                         if !status.success() {
-                            return Err(Self::status_to_io_error(status, cmd, file, line));
+                            let err = Self::status_to_io_error(status, cmd, file, line);
+                            return (Err(err), status.code());
                         }
+                        (Ok(()), status.code())
                     }
                 }
             }
-            CmdChildHandle::Thread(thread) => {
-                let status = thread.join();
-                match status {
-                    Ok(result) => {
-                        if let Err(e) = result {
-                            return Err(process::new_cmd_io_error(&e, cmd, file, line));
-                        }
-                    }
-                    Err(e) => {
-                        return Err(Error::new(
-                            ErrorKind::Other,
-                            format!(
-                                "Running [{cmd}] thread joined with error: {e:?} at {file}:{line}"
-                            ),
-                        ))
-                    }
-                }
+            CmdChildHandle::Thread(thread) => Self::joined_result(&thread.join(), cmd, file, line),
+            CmdChildHandle::Joined(status) => Self::joined_result(&status, cmd, file, line),
+            CmdChildHandle::SyncFn => (Ok(()), Some(0)),
+        }
+    }
+
+    fn joined_result(
+        status: &thread::Result<CmdResult>,
+        cmd: &str,
+        file: &str,
+        line: u32,
+    ) -> (CmdResult, Option<i32>) {
+        match status {
+            Ok(Ok(())) => (Ok(()), Some(0)),
+            Ok(Err(e)) => {
+                let code = ExitWithError::code_of(e).unwrap_or(1);
+                (
+                    Err(process::new_cmd_io_error(e, cmd, file, line)),
+                    Some(code),
+                )
             }
-            CmdChildHandle::SyncFn => {}
+            Err(e) => (
+                Err(Error::other(format!(
+                    "Running [{cmd}] thread joined with error: {e:?} at {file}:{line}"
+                ))),
+                None,
+            ),
+        }
+    }
+
+    // polls the exit status without blocking, returning the error if it already failed
+    fn try_wait_error(&mut self, cmd: &str, file: &str, line: u32) -> Option<Error> {
+        if let CmdChildHandle::Thread(thread) = self {
+            if !thread.is_finished() {
+                return None;
+            }
+            // join the finished thread to get its result, keeping it for `wait()`
+            if let CmdChildHandle::Thread(thread) = std::mem::replace(self, CmdChildHandle::SyncFn)
+            {
+                *self = CmdChildHandle::Joined(thread.join());
+            }
+        }
+        match self {
+            CmdChildHandle::Proc(proc) => match proc.try_wait() {
+                Ok(Some(status)) if !status.success() => {
+                    Some(Self::status_to_io_error(status, cmd, file, line))
+                }
+                Ok(_) => None,
+                Err(e) => Some(process::new_cmd_io_error(&e, cmd, file, line)),
+            },
+            CmdChildHandle::Joined(status) => Self::joined_result(status, cmd, file, line).0.err(),
+            CmdChildHandle::Thread(_) | CmdChildHandle::SyncFn => None,
         }
-        Ok(())
     }
 
     fn status_to_io_error(status: ExitStatus, cmd: &str, file: &str, line: u32) -> Error {
         if let Some(code) = status.code() {
-            Error::new(
-                ErrorKind::Other,
-                format!("Running [{cmd}] exited with error; status code: {code} at {file}:{line}"),
-            )
+            Error::other(format!(
+                "Running [{cmd}] exited with error; status code: {code} at {file}:{line}"
+            ))
         } else {
-            Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Running [{cmd}] exited with error; terminated by {status} at {file}:{line}"
-                ),
-            )
+            Error::other(format!(
+                "Running [{cmd}] exited with error; terminated by {status} at {file}:{line}"
+            ))
         }
     }
 
     fn kill(self, cmd: &str, file: &str, line: u32) -> CmdResult {
         match self {
-            CmdChildHandle::Proc(mut proc) => proc.kill().map_err(|e| {
-                Error::new(
-                    e.kind(),
-                    format!("Killing process [{cmd}] failed with error: {e} at {file}:{line}"),
-                )
-            }),
-            CmdChildHandle::Thread(_thread) => Err(Error::new(
-                ErrorKind::Other,
-                format!("Killing thread [{cmd}] failed: not supported at {file}:{line}"),
-            )),
+            CmdChildHandle::Proc(mut proc) => proc
+                .kill()
+                .and_then(|_| proc.wait())
+                .map(|_| ())
+                .map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!("Killing process [{cmd}] failed with error: {e} at {file}:{line}"),
+                    )
+                }),
+            CmdChildHandle::Joined(_) => Ok(()),
+            CmdChildHandle::Thread(_thread) => Err(Error::other(format!(
+                "Killing thread [{cmd}] failed: not supported at {file}:{line}"
+            ))),
             CmdChildHandle::SyncFn => Ok(()),
         }
     }
 
+    fn try_wait(&mut self) -> Result<bool> {
+        match self {
+            CmdChildHandle::Proc(proc) => proc.try_wait().map(|status| status.is_some()),
+            CmdChildHandle::Thread(thread) => Ok(thread.is_finished()),
+            CmdChildHandle::Joined(_) | CmdChildHandle::SyncFn => Ok(true),
+        }
+    }
+
     fn pid(&self) -> Option<u32> {
         match self {
             CmdChildHandle::Proc(proc) => Some(proc.id()),
@@ -328,6 +1132,29 @@ impl CmdChildHandle {
     }
 }
 
+// whether the pipe has no room left, so its writers block until it is read
+#[cfg(target_os = "linux")]
+fn pipe_is_full(fd: RawFd) -> bool {
+    let mut available: libc::c_int = 0;
+    // SAFETY: `fd` is a valid pipe descriptor for the caller, and `available` outlives the call
+    unsafe {
+        let capacity = libc::fcntl(fd, libc::F_GETPIPE_SZ);
+        capacity > 0
+            && libc::ioctl(fd, libc::FIONREAD, &mut available) == 0
+            && available >= capacity
+    }
+}
+
+// appends the last stderr lines kept by a non-capturing StderrThread to the error of a command
+fn with_stderr_tail(e: Error, tail: String) -> Error {
+    if tail.is_empty() {
+        return e;
+    }
+    rewrap_error(&e, format!("{e}; last stderr lines:\n{tail}"))
+}
+
+// Logs stderr lines, or captures them if `capture` is set. When logging, the last
+// `error_stderr_lines()` lines are kept and returned by `join()`, to be shown in errors.
 struct StderrThread {
     thread: Option<JoinHandle<String>>,
     cmd: String,
@@ -336,23 +1163,71 @@ struct StderrThread {
 }
 
 impl StderrThread {
-    fn new(cmd: &str, file: &str, line: u32, stderr: Option<PipeReader>, capture: bool) -> Self {
+    fn new(
+        cmd: &str,
+        file: &str,
+        line: u32,
+        stderr: Option<PipeReader>,
+        label: Option<String>,
+        capture: bool,
+        stop: Option<StderrStop>,
+    ) -> Self {
         if let Some(stderr) = stderr {
+            let limit = process::stderr_capture_limit();
+            let tail_lines = process::error_stderr_lines();
+            let label = label.or_else(process::current_label);
             let thread = std::thread::spawn(move || {
                 let mut output = String::new();
-                BufReader::new(stderr)
-                    .lines()
-                    .map_while(Result::ok)
-                    .for_each(|line| {
-                        if !capture {
-                            info!("{line}");
+                let mut truncated = false;
+                let mut tail = VecDeque::with_capacity(tail_lines);
+                let mut reader = BufReader::new(stderr);
+                let mut buf = vec![];
+                loop {
+                    // keep enough of a captured line to tell it is over the limit, and nothing
+                    // once truncated, so a long line without newline can't exhaust memory
+                    let max = match limit {
+                        Some(_) if capture && truncated && stop.is_none() => 0,
+                        Some(limit) if capture => limit.saturating_add(1),
+                        _ => usize::MAX,
+                    };
+                    match read_line_bounded(&mut reader, &mut buf, max) {
+                        Ok(true) => {}
+                        _ => break,
+                    }
+                    let line = String::from_utf8_lossy(&buf).into_owned();
+                    if let Some((ref matches, ref tx)) = stop {
+                        if matches(&line) {
+                            let _ = tx.send(line.clone());
+                        }
+                    }
+                    if !capture {
+                        if let Some(ref label) = label {
+                            info!("[{label}] {line}");
                         } else {
-                            if !output.is_empty() {
-                                output.push('\n');
+                            info!("{line}");
+                        }
+                        if tail_lines > 0 {
+                            if tail.len() == tail_lines {
+                                tail.pop_front();
                             }
+                            tail.push_back(line);
+                        }
+                    } else if !truncated {
+                        if !output.is_empty() {
+                            output.push('\n');
+                        }
+                        if limit.is_some_and(|limit| output.len() + line.len() > limit) {
+                            // keep draining the pipe, but drop the rest of the output
+                            output.push_str(STDERR_TRUNCATED);
+                            truncated = true;
+                        } else {
                             output.push_str(&line);
                         }
-                    });
+                    }
+                }
+                if !capture {
+                    output = Vec::from(tail).join("\n");
+                }
                 output
             });
             Self {
@@ -385,6 +1260,43 @@ impl StderrThread {
         }
         "".into()
     }
+
+    // stops waiting for the thread once the children are killed, since their stderr pipe may be
+    // kept open by grandchildren processes, like `sleep` in `sh -c "sleep 5"`
+    fn detach(&mut self) {
+        self.thread.take();
+    }
+}
+
+// reads a line without its line ending into `buf`, keeping at most `max` bytes of it and
+// discarding the rest, and returns false at the end of the stream
+fn read_line_bounded(reader: &mut impl BufRead, buf: &mut Vec<u8>, max: usize) -> Result<bool> {
+    buf.clear();
+    let mut read = false;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok(read);
+        }
+        read = true;
+        let (chunk, used, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(i) => (&available[..i], i + 1, true),
+            None => (available, available.len(), false),
+        };
+        let keep = chunk.len().min(max.saturating_sub(buf.len()));
+        buf.extend_from_slice(&chunk[..keep]);
+        reader.consume(used);
+        if done {
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+            return Ok(true);
+        }
+    }
 }
 
 impl Drop for StderrThread {
@@ -392,3 +1304,52 @@ impl Drop for StderrThread {
         self.join();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_line_bounded() {
+        let mut reader = BufReader::with_capacity(4, "abcdefghij\r\nxy\nz".as_bytes());
+        let mut buf = vec![];
+        assert!(read_line_bounded(&mut reader, &mut buf, 3).unwrap());
+        assert_eq!(buf, b"abc");
+        assert!(read_line_bounded(&mut reader, &mut buf, 3).unwrap());
+        assert_eq!(buf, b"xy");
+        assert!(read_line_bounded(&mut reader, &mut buf, 0).unwrap());
+        assert_eq!(buf, b"");
+        assert!(!read_line_bounded(&mut reader, &mut buf, 3).unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_pipe_is_full() {
+        let (reader, mut writer) = os_pipe::pipe().unwrap();
+        assert!(!pipe_is_full(reader.as_raw_fd()));
+        writer.write_all(b"x").unwrap();
+        assert!(!pipe_is_full(reader.as_raw_fd()));
+
+        let capacity = unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_GETPIPE_SZ) };
+        writer.write_all(&vec![0; capacity as usize - 1]).unwrap();
+        assert!(pipe_is_full(reader.as_raw_fd()));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_stdout_watchdog_keeps_no_pipe_open() {
+        use crate::{Cmd, Cmds, GroupCmds};
+        let now = Instant::now();
+        let mut chunks = GroupCmds::default()
+            .append(Cmds::default().pipe(Cmd::default().add_args(["yes"])))
+            .spawn(true)
+            .unwrap()
+            .into_fun_children()
+            .with_stdout_watchdog()
+            .chunks(4);
+        assert_eq!(chunks.next().unwrap().unwrap(), b"y\ny\n");
+        // `yes` gets SIGPIPE once the iterator closes stdout
+        drop(chunks);
+        assert!(now.elapsed() < Duration::from_secs(2));
+    }
+}
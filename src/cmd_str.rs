@@ -0,0 +1,344 @@
+use crate::process::{Cmd, Cmds, GroupCmds, Redirect};
+use crate::{CmdResult, FunResult};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/// Variables available to [`run_cmd_str()`] and [`run_fun_str()`], the runtime counterpart of the
+/// variables captured by the macros.
+///
+/// A value set with [`set()`](SymTable::set) is always passed as one argument, and a value set
+/// with [`set_vec()`](SymTable::set_vec) expands to one argument per element with `$[name]`.
+#[derive(Default, Clone, Debug)]
+pub struct SymTable {
+    vars: HashMap<String, Sym>,
+}
+
+#[derive(Clone, Debug)]
+enum Sym {
+    Str(OsString),
+    Vec(Vec<OsString>),
+}
+
+impl SymTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `name` to a single value, referenced as `$name` or `${name}`.
+    pub fn set<V: AsRef<OsStr>>(&mut self, name: &str, value: V) -> &mut Self {
+        let value = value.as_ref().to_os_string();
+        self.vars.insert(name.into(), Sym::Str(value));
+        self
+    }
+
+    /// Sets `name` to a list of values, referenced as `$[name]`.
+    pub fn set_vec<I, V>(&mut self, name: &str, values: I) -> &mut Self
+    where
+        I: IntoIterator<Item = V>,
+        V: AsRef<OsStr>,
+    {
+        let values = values.into_iter().map(|v| v.as_ref().into()).collect();
+        self.vars.insert(name.into(), Sym::Vec(values));
+        self
+    }
+}
+
+/// Parses `cmds` at runtime and runs it like [`run_cmd!`](crate::run_cmd), taking variables from
+/// `syms` instead of the caller's scope.
+///
+/// This is meant for commands that only exist as text at runtime, like in a REPL or a script
+/// file. Words are split on whitespace, and `'...'`, `"..."` and `\` quote like in the macros.
+/// `|`, `;` or newlines, `#` comments and the `<`, `<<`, `>`, `>>`, `>|`, `2>`, `&>` and `2>&1`
+/// style redirects are supported, while anything else, like `$(...)` or globs, is kept literally.
+///
+/// Variables are substituted after the command is split into words, so a value never turns into
+/// more arguments, a pipe, a redirect, an env assignment like `LD_PRELOAD=...` or a modifier like
+/// `quiet_stderr`, whatever it contains, while `KEY=$value` written in `cmds` still sets `KEY`.
+/// Always pass untrusted input through `syms`: formatting it into `cmds` lets it inject arbitrary
+/// commands.
+/// ```no_run
+/// # use cmd_lib::{run_cmd_str, SymTable};
+/// let mut syms = SymTable::new();
+/// syms.set("dir", "/tmp/my dir").set_vec("opts", ["-l", "-a"]);
+/// run_cmd_str("mkdir -p $dir; ls $[opts] ${dir} | wc -l", &syms)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[track_caller]
+pub fn run_cmd_str(cmds: &str, syms: &SymTable) -> CmdResult {
+    let location = std::panic::Location::caller();
+    parse_cmd_str(cmds, syms, location.file(), location.line())?.run_cmd()
+}
+
+/// Parses `cmds` at runtime and runs it like [`run_fun!`](crate::run_fun), returning its output.
+///
+/// See [`run_cmd_str()`] for the supported syntax and why variables belong in `syms`.
+#[track_caller]
+pub fn run_fun_str(cmds: &str, syms: &SymTable) -> FunResult {
+    let location = std::panic::Location::caller();
+    parse_cmd_str(cmds, syms, location.file(), location.line())?.run_fun()
+}
+
+fn parse_cmd_str(cmds: &str, syms: &SymTable, file: &str, line: u32) -> Result<GroupCmds<'static>> {
+    CmdStrParser {
+        chars: cmds.chars().collect(),
+        pos: 0,
+        syms,
+        file,
+        line,
+    }
+    .parse()
+}
+
+struct CmdStrParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    syms: &'a SymTable,
+    file: &'a str,
+    line: u32,
+}
+
+impl CmdStrParser<'_> {
+    fn parse(mut self) -> Result<GroupCmds<'static>> {
+        let mut group = GroupCmds::default();
+        let mut cmds = Cmds::default();
+        let mut cmd = self.new_cmd();
+        let (mut cmd_empty, mut cmds_empty) = (true, true);
+        loop {
+            while matches!(self.peek(), Some(' ' | '\t' | '\r')) {
+                self.pos += 1;
+            }
+            match self.peek() {
+                None | Some(';' | '\n') => {
+                    if !cmd_empty {
+                        cmds = cmds.pipe(cmd);
+                        cmds_empty = false;
+                    } else if !cmds_empty {
+                        return Err(self.error("missing command after '|'"));
+                    }
+                    if !cmds_empty {
+                        group = group.append(cmds);
+                    }
+                    if self.next().is_none() {
+                        return Ok(group);
+                    }
+                    cmds = Cmds::default();
+                    cmd = self.new_cmd();
+                    (cmd_empty, cmds_empty) = (true, true);
+                }
+                Some('|') => {
+                    if cmd_empty {
+                        return Err(self.error("missing command before '|'"));
+                    }
+                    self.pos += 1;
+                    cmds = cmds.pipe(cmd);
+                    cmd = self.new_cmd();
+                    (cmd_empty, cmds_empty) = (true, false);
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => {
+                    if let Some(redirects) = self.parse_redirect()? {
+                        for redirect in redirects {
+                            cmd = cmd.add_redirect(redirect);
+                        }
+                    } else {
+                        let (words, literal) = self.parse_word()?;
+                        for word in words {
+                            cmd = if literal {
+                                cmd.add_literal_arg(word)
+                            } else {
+                                cmd.add_arg(word)
+                            };
+                        }
+                    }
+                    cmd_empty = false;
+                }
+            }
+        }
+    }
+
+    fn new_cmd(&self) -> Cmd {
+        Cmd::default().with_location(self.file, self.line)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek();
+        self.pos += 1;
+        ch
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let end = self.pos + s.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(s.chars()) {
+            self.pos = end;
+            return true;
+        }
+        false
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{msg} at {}:{}", self.file, self.line),
+        )
+    }
+
+    fn parse_redirect(&mut self) -> Result<Option<Vec<Redirect>>> {
+        if self.eat("<<") {
+            return Ok(Some(vec![Redirect::HereDoc(self.parse_target()?)]));
+        }
+        if self.eat("<") {
+            let path = self.parse_target()?.into();
+            return Ok(Some(vec![Redirect::FileToStdin(path)]));
+        }
+        let fd = if self.eat("&>") {
+            0
+        } else if self.eat("2>") {
+            2
+        } else if self.eat("1>") || self.eat(">") {
+            1
+        } else {
+            return Ok(None);
+        };
+        if fd != 0 && self.eat("&") {
+            return match (fd, self.next()) {
+                (1, Some('2')) => Ok(Some(vec![Redirect::StdoutToStderr])),
+                (2, Some('1')) => Ok(Some(vec![Redirect::StderrToStdout])),
+                (_, Some('1' | '2')) => Ok(Some(vec![])),
+                _ => Err(self.error("expect &1 or &2")),
+            };
+        }
+        let append = self.eat(">");
+        let force = !append && self.eat("|");
+        let path = PathBuf::from(self.parse_target()?);
+        Ok(Some(match fd {
+            1 => vec![Redirect::StdoutToFile(path, append, force)],
+            2 => vec![Redirect::StderrToFile(path, append, force)],
+            _ => vec![
+                Redirect::StdoutToFile(path, append, force),
+                Redirect::StderrToStdout,
+            ],
+        }))
+    }
+
+    fn parse_target(&mut self) -> Result<OsString> {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+        let (mut words, _) = self.parse_word()?;
+        match (words.pop(), words.is_empty()) {
+            (Some(word), true) if !word.is_empty() => Ok(word),
+            _ => Err(self.error("wrong redirection format: missing target")),
+        }
+    }
+
+    // also returns whether the words must be passed literally, because a variable decides whether
+    // they look like an env assignment or a modifier, like `quiet_stderr`
+    fn parse_word(&mut self) -> Result<(Vec<OsString>, bool)> {
+        if self.eat("$[") {
+            let name = self.parse_name(']')?;
+            if !self.at_word_end() {
+                return Err(self.error(&format!("$[{name}] must be a whole word")));
+            }
+            return match self.syms.vars.get(&name) {
+                Some(Sym::Vec(values)) => Ok((values.clone(), true)),
+                Some(Sym::Str(value)) => Ok((vec![value.clone()], true)),
+                None => Err(self.error(&format!("undefined variable: {name}"))),
+            };
+        }
+        let mut word = OsString::new();
+        // the literal text before the first variable, if any
+        let mut prefix = None;
+        while !self.at_word_end() {
+            match self.next() {
+                Some('\'') => loop {
+                    match self.next() {
+                        Some('\'') => break,
+                        Some(ch) => word.push(ch.encode_utf8(&mut [0; 4])),
+                        None => return Err(self.error("unterminated single quote")),
+                    }
+                },
+                Some('"') => loop {
+                    match self.next() {
+                        Some('"') => break,
+                        Some('\\') if matches!(self.peek(), Some('"' | '\\' | '$')) => {
+                            let ch = self.next().unwrap();
+                            word.push(ch.encode_utf8(&mut [0; 4]));
+                        }
+                        Some('$') => {
+                            prefix.get_or_insert_with(|| word.clone());
+                            word.push(self.parse_var()?);
+                        }
+                        Some(ch) => word.push(ch.encode_utf8(&mut [0; 4])),
+                        None => return Err(self.error("unterminated double quote")),
+                    }
+                },
+                Some('\\') => match self.next() {
+                    Some(ch) => word.push(ch.encode_utf8(&mut [0; 4])),
+                    None => return Err(self.error("dangling '\\' at the end")),
+                },
+                Some('$') => {
+                    prefix.get_or_insert_with(|| word.clone());
+                    word.push(self.parse_var()?);
+                }
+                Some(ch) => word.push(ch.encode_utf8(&mut [0; 4])),
+                None => break,
+            }
+        }
+        // `KEY=$value` is still an assignment, as `KEY=` is written literally
+        let literal = prefix.is_some_and(|prefix| {
+            !matches!(prefix.to_string_lossy().split_once('='), Some((key, _))
+                if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        });
+        Ok((vec![word], literal))
+    }
+
+    fn at_word_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(' ' | '\t' | '\r' | '\n' | ';' | '|' | '<' | '>')
+        )
+    }
+
+    // `$` is already consumed, a `$` not followed by a name is kept literally
+    fn parse_var(&mut self) -> Result<OsString> {
+        let name = if self.eat("{") {
+            self.parse_name('}')?
+        } else {
+            let start = self.pos;
+            while matches!(self.peek(), Some(ch) if ch.is_ascii_alphanumeric() || ch == '_') {
+                self.pos += 1;
+            }
+            if start == self.pos {
+                return Ok("$".into());
+            }
+            self.chars[start..self.pos].iter().collect()
+        };
+        match self.syms.vars.get(&name) {
+            Some(Sym::Str(value)) => Ok(value.clone()),
+            Some(Sym::Vec(_)) => Err(self.error(&format!("use $[{name}] for vector variable"))),
+            None => Err(self.error(&format!("undefined variable: {name}"))),
+        }
+    }
+
+    fn parse_name(&mut self, close: char) -> Result<String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(ch) if ch.is_ascii_alphanumeric() || ch == '_') {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if name.is_empty() || self.next() != Some(close) {
+            return Err(self.error(&format!("bad variable name, expect name and '{close}'")));
+        }
+        Ok(name)
+    }
+}
@@ -123,6 +123,27 @@
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
+//! The trailing newline of the output is trimmed, use
+//! [`run_fun_untrimmed!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.run_fun_untrimmed.html)
+//! to keep the output as is, and
+//! [`run_fun_plain!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.run_fun_plain.html)
+//! to strip ANSI escape sequences, like colors, from the output.
+//! [`set_output_trim()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_output_trim.html) changes
+//! the trimming, e.g. to also remove a trailing `\r\n` or all trailing whitespace.
+//!
+//! These macros accept a leading `#[timeout(<duration>)]` modifier, with a duration in `ms`, `s`,
+//! `m` or `h`, or a `Duration` variable. Once all the commands together run longer than that,
+//! the running command is killed, and an error with
+//! [`ErrorKind::TimedOut`](https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.TimedOut)
+//! is returned:
+//! ```no_run
+//! # use cmd_lib::*;
+//! run_cmd!(#[timeout(30s)] cargo build)?;
+//! let limit = std::time::Duration::from_millis(1500);
+//! let refs = run_fun!(#[timeout(limit)] git ls-remote origin)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! ### Abstraction without overhead
 //!
 //! Since all the macros' lexical analysis and syntactic analysis happen at compile time, it can
@@ -152,6 +173,68 @@
 //! ```
 //! You can consider "" as glue, so everything inside the quotes will be treated as a single atomic component.
 //!
+//! Like in bash, unquoted braces with commas are expanded into multiple arguments, which is pure
+//! string expansion without touching the filesystem. Braces without commas are left as is:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(touch /tmp/file.{txt,md})?; // touch /tmp/file.txt /tmp/file.md
+//! run_cmd!(cp /tmp/file.txt{,.bak})?; // cp /tmp/file.txt /tmp/file.txt.bak
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Command substitution `$(...)` runs the inner commands, and uses their output without trailing
+//! newlines as a single argument, even if it has multiple lines, like `"$(...)"` in bash.
+//! Substitutions are run right before their own statement, in the directory and with the
+//! variables set by the previous statements, and never for a statement skipped by `when`.
+//! Their errors are returned as the result of the macro:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(echo "Built at" $(date +%Y-%m-%d))?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! To split the output into multiple arguments on whitespaces instead, like unquoted `$(...)` in
+//! bash, use `$[(...)]`, the same as for vector variables:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(kill $[(pgrep -f my_server)])?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Any variable implementing [`Display`](https://doc.rust-lang.org/std/fmt/trait.Display.html) can be
+//! interpolated, such as integers, floats and bools. Floats are printed in plain decimal notation without
+//! exponent, e.g. `1e-7` becomes `0.0000001`. Use `format!` if you need a fixed precision:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let count = 3;
+//! let interval = 0.5;
+//! let verbose = true;
+//! run_cmd!(ping -c $count -i $interval localhost)?;
+//! run_cmd!(echo "verbose: $verbose")?;
+//! let ratio = format!("{:.2}", 2.0 / 3.0);
+//! run_cmd!(echo $ratio)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`Duration`](https://doc.rust-lang.org/std/time/struct.Duration.html) is interpolated as decimal
+//! seconds, which is what `sleep` and `timeout` expect:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let delay = std::time::Duration::from_millis(1500);
+//! run_cmd!(sleep $delay)?; // sleep 1.5
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! On unix, byte slices such as `&[u8]` and `Vec<u8>` can be interpolated too, and are passed as is,
+//! even if they are not valid UTF-8. This is not supported on Windows, where arguments must be
+//! valid Unicode:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let name: &[u8] = b"caf\xe9.txt";
+//! run_cmd!(touch /tmp/$name)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! If they are part of [Raw string literals](https://doc.rust-lang.org/reference/tokens.html#raw-string-literals),
 //! there will be no string interpolation, the same as in idiomatic rust. However, you can always use `format!` macro
 //! to form the new string. For example:
@@ -165,6 +248,15 @@
 //! ```
 //! Notice here `$awk_opts` will be treated as single option passing to awk command.
 //!
+//! Or prefix the raw string with `$` to opt in interpolation, while keeping backslashes as is.
+//! Use `$$` for a literal `$` there:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let key_word = "time";
+//! run_cmd!(ping -c 10 www.google.com | awk $r#"/$key_word/ {print $$(NF-3) " " $$(NF-1) " " $$NF}"#)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! If you want to use dynamic parameters, you can use `$[]` to access vector variable:
 //! ```no_run
 //! # use cmd_lib::run_cmd;
@@ -175,10 +267,59 @@
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
+//! To pass the elements as a single argument instead, joined with a separator, put the separator
+//! after a `;`, which also works as part of a longer argument:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let features = vec!["serde", "regex"];
+//! run_cmd!(cargo build --features $[features; ","])?; // --features serde,regex
+//! run_cmd!(cargo test --features=$[features; ","])?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! ### Redirection and Piping
 //! Right now piping and stdin, stdout, stderr redirection are supported. Most parts are the same as in
 //! [bash scripts](https://www.gnu.org/software/bash/manual/html_node/Redirections.html#Redirections).
 //!
+//! Like in bash, `|&` is a shorthand of `2>&1 |`, which pipes both stdout and stderr into the next command:
+//! ```no_run
+//! # use cmd_lib::run_fun;
+//! let warnings = run_fun!(cargo build |& grep warning)?;
+//! let warnings = run_fun!(cargo build 2>&1 | grep warning)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Instead of heredocs, `<<` takes the following string as stdin content, and a raw string literal
+//! can be used for multiple lines:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(tee /tmp/config.toml << r#"
+//! [server]
+//! port = 8080
+//! "#)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Redirect targets are built the same way as command arguments, so they can be `Path`/`PathBuf`
+//! variables, or be joined from variables, literals and command substitutions:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let log_dir = std::env::temp_dir();
+//! run_cmd!(cargo build &> $log_dir/build.log)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! After calling [`set_noclobber(true)`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_noclobber.html),
+//! `>` refuses to overwrite existing files, like `set -o noclobber` in bash, and `>|` overwrites
+//! them anyway:
+//! ```no_run
+//! # use cmd_lib::*;
+//! set_noclobber(true);
+//! run_cmd!(date > /tmp/stamp)?; // fails if /tmp/stamp exists
+//! run_cmd!(date >| /tmp/stamp)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! ### Logging
 //!
 //! This library provides convenient macros and builtin commands for logging. All messages which
@@ -191,8 +332,8 @@
 //! run_cmd!(mkdir /tmp/$dir; ls /tmp/$dir)?;
 //! run_cmd!(mkdir /tmp/$dir; ls /tmp/$dir; rmdir /tmp/$dir)?;
 //! // output:
-//! // [INFO ] mkdir: cannot create directory ‘/tmp/folder with spaces’: File exists
-//! // Error: Running ["mkdir" "/tmp/folder with spaces"] exited with error; status code: 1
+//! // [INFO ] mkdir: cannot create directory "/tmp/folder with spaces": File exists (os error 17)
+//! // Error: statement 1/3: Running ["mkdir" "/tmp/folder with spaces"] failed: mkdir: cannot create directory "/tmp/folder with spaces"
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
@@ -200,10 +341,30 @@
 //! logger implementation. Notice that if you don't provide any logger, it will use env_logger to print
 //! messages from process's stderr.
 //!
+//! The format of this default logger can be adjusted with
+//! [`set_default_logger_builder()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_default_logger_builder.html),
+//! before any message is logged. If the stderr of the program is closed, like with
+//! `myprog 2>&1 | head`, the default logger drops the messages quietly, while commands keep running.
+//!
+//! To show why a command failed right in its error, e.g. in CI logs, calling
+//! [`set_error_stderr_lines(n)`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_error_stderr_lines.html)
+//! appends its last `n` stderr lines to the error message.
+//!
+//! If logging is not needed, calling [`set_inherit_stderr(true)`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_inherit_stderr.html)
+//! lets commands write to the parent's stderr directly, which saves a pipe and a logging thread
+//! for each command.
+//!
+//! Like `bash -x`, calling [`set_xtrace(true)`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_xtrace.html)
+//! logs each command with a `+ ` prefix right before it runs, in the current thread:
+//! ```console
+//! [INFO ] + ls
+//! [INFO ] + wc -l
+//! ```
+//!
 //! You can also mark your `main()` function with `#[cmd_lib::main]`, which will log error from
 //! main() by default. Like this:
 //! ```console
-//! [ERROR] FATAL: Running ["mkdir" "/tmp/folder with spaces"] exited with error; status code: 1
+//! [ERROR] FATAL: statement 1/3: Running ["mkdir" "/tmp/folder with spaces"] failed: mkdir: cannot create directory "/tmp/folder with spaces"
 //! ```
 //!
 //! ### Builtin commands
@@ -224,16 +385,221 @@
 //! Use `std::env::set_current_dir` if you want to change the current
 //! working directory for the whole program.
 //!
+//! With `-p` or `--create`, `cd` creates the directory and its parents if they are missing,
+//! while plain `cd` fails:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd! (
+//!     cd -p /tmp/build/output;
+//!     touch result.txt;
+//! )?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! To run a single command in another directory, without changing it for the following
+//! commands, put `in <dir>` before the command:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd! (
+//!     in /tmp ls | wc -l;
+//!     ls | wc -l;
+//! )?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### export
+//! export: set environment variables for all the following commands in the same macro call,
+//! like `export` in bash, while `KEY=VALUE cmd` only sets them for one command.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd! (
+//!     export RUSTFLAGS="-D warnings" CARGO_TERM_COLOR=always;
+//!     cargo build;
+//!     CARGO_TERM_COLOR=never cargo test;
+//! )?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//! Like `cd`, the variables are dropped when the macro call returns, and the environment of
+//! the current process is never changed. `export` must be a statement on its own, so it fails
+//! in a pipe or with [`spawn!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.spawn.html).
+//!
 //! #### ignore
 //!
 //! Ignore errors for command execution.
 //!
+//! #### raw
+//!
+//! Pass the arguments of the following command as is, without quoting them. On Windows this is
+//! an escape hatch for programs with their own command line parsing, like `cmd /c`, and the
+//! caller is responsible for escaping the arguments. It makes no difference on other platforms.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let input = r#"echo "hello world""#;
+//! run_cmd!(raw cmd /c $input)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### quiet_stderr
+//!
+//! Discard stderr of the following command, without logging it, to keep a noisy step quiet.
+//! Unlike `ignore`, errors are still reported.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(quiet_stderr cargo build | grep warning)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### quiet_stdout, show_stdout
+//!
+//! Discard stdout of the following command, or write it to the parent's stdout directly, for
+//! each statement independently. With `show_stdout`, the output is not captured by `run_fun!`:
+//! ```no_run
+//! # use cmd_lib::run_fun;
+//! let version = run_fun! (
+//!     quiet_stdout cargo fetch;
+//!     show_stdout echo "Checking version ...";
+//!     rustc --version;
+//! )?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### show_stderr
+//!
+//! Write stderr of the following command to the parent's stderr directly, instead of logging it,
+//! e.g. to keep the live progress of a tool on the terminal while capturing its stdout. Like in
+//! shells, `2>&2` does the same:
+//! ```no_run
+//! # use cmd_lib::run_fun;
+//! let image = run_fun!(show_stderr docker build -q .)?;
+//! let digest = run_fun!(docker push $image 2>&2 | tail -n 1)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### swap_out_err
+//!
+//! Swap stdout and stderr of the following command, like `3>&1 1>&2 2>&3` in bash, so its stdout
+//! is logged like stderr usually is, and its stderr is piped or captured instead. Redirects of
+//! the command still apply to its own stdout and stderr:
+//! ```no_run
+//! # use cmd_lib::run_fun;
+//! let warnings = run_fun!(swap_out_err cargo build --message-format short | grep -c warning)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### when
+//!
+//! Run the following statement only if the bool variable is true, to keep optional steps inline.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! let verbose = true;
+//! run_cmd! (
+//!     cargo build;
+//!     when $verbose ls -l target/debug;
+//! )?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### umask
+//!
+//! Set the file mode creation mask (in octal) of the following command, on unix. It also
+//! applies to files created by its redirections.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(umask 077 echo "secret" > /tmp/token)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### label
+//!
+//! Log a `=== <label> ===` line, and prefix the following logs of commands in the current thread
+//! with `[<label>]`, to group them under named phases. Run `label` without arguments to clear it.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd! (
+//!     label "build";
+//!     cargo build;
+//!     label "test";
+//!     cargo test;
+//!     label;
+//! )?;
+//! // output:
+//! // [INFO ] === build ===
+//! // [INFO ] [build]    Compiling ...
+//! // [INFO ] === test ===
+//! // [INFO ] [test]     Finished ...
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Followed by `--` and a command, a quoted `label "<label>" --` only applies to that command, and
+//! its stdout lines are prefixed with `[<label>]` too, to tell apart the output of commands running
+//! side by side:
+//! ```no_run
+//! # use cmd_lib::spawn;
+//! let mut build = spawn!(label "build" -- cargo build)?;
+//! let mut test = spawn!(label "test" -- cargo test)?;
+//! build.wait()?;
+//! test.wait()?;
+//! // output:
+//! // [INFO ] [build]    Compiling ...
+//! // [test] running 79 tests
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! A quoted label followed by a command without `--` fails to compile, instead of taking the
+//! command as part of the label:
+//! ```compile_fail
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(label "build" cargo build)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! #### echo
 //! Print messages to stdout.
 //! ```console
 //! -n     do not output the trailing newline
 //! ```
 //!
+//! #### mkdir
+//! Create directories without spawning an external process. With any other option, like
+//! `mkdir -m 700`, the system `mkdir` is run instead.
+//! ```console
+//! -p     no error if existing, make parent directories as needed
+//! ```
+//!
+//! #### tee
+//! Copy stdin to stdout and to each of the files, without spawning an external process. With any
+//! other option, like `tee -i`, the system `tee` is run instead.
+//! ```console
+//! -a     append to the files, do not overwrite
+//! ```
+//!
+//! #### touch
+//! Create the files if they do not exist, and update their modification time to now, without
+//! spawning an external process. With any other option, like `touch -d`, the system `touch` is
+//! run instead.
+//! ```console
+//! -c     do not create any files
+//! ```
+//!
+//! #### cmp
+//!
+//! Compare two files byte by byte without spawning a process, failing if they differ. The offset
+//! of the first difference is printed to stderr, unless `-s` is given. With any other option, like
+//! `cmp -l`, the system `cmp` is run instead.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! run_cmd!(cmp expected.bin actual.bin)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! #### exit-with
+//! Exit with the given status code, without spawning an external process, which is handy to
+//! test error handling, like `pipefail`.
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! assert!(run_cmd!(exit-with 7 | cat).is_err());
+//! ```
+//!
 //! #### error, warn, info, debug, trace
 //!
 //! Print messages to logging with different levels. You can also use the normal logging macros,
@@ -251,18 +617,58 @@
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
+//! If the first argument has printf-style conversions (`%s`, `%d`, `%f`, `%x`, ...) and more
+//! arguments follow, they are formatted into it. Otherwise all arguments are joined with spaces:
+//! ```no_run
+//! # use cmd_lib::*;
+//! let (n, ratio) = (3, 0.25);
+//! run_cmd!(info "count: %d, ratio: %.1f" $n $ratio)?;
+//! // output:
+//! // [INFO ] count: 3, ratio: 0.2
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! For a batch of commands built at runtime, [`run_cmds()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.run_cmds.html)
+//! runs each of them as a separate statement, without formatting them into strings:
+//! ```no_run
+//! # use cmd_lib::run_cmds;
+//! let cmds: Vec<Vec<String>> = vec![vec!["ls".into(), "-l".into()], vec!["date".into()]];
+//! run_cmds(&cmds)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! When the command syntax itself only exists at runtime, like in a REPL,
+//! [`run_cmd_str()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.run_cmd_str.html) and
+//! [`run_fun_str()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.run_fun_str.html) parse it from a
+//! string, with variables taken from an explicit
+//! [`SymTable`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.SymTable.html). Values from the
+//! table are substituted after parsing, so never format untrusted input into the string itself:
+//! ```no_run
+//! # use cmd_lib::{run_fun_str, SymTable};
+//! let mut syms = SymTable::new();
+//! syms.set("pattern", "user input; rm -rf /");
+//! let matches = run_fun_str("grep -c $pattern /etc/passwd", &syms)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! ### Low-level process spawning macros
 //!
 //! [`spawn!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.spawn.html) macro executes the whole command as a child process, returning a handle to it. By
 //! default, stdin, stdout and stderr are inherited from the parent. The process will run in the
 //! background, so you can run other stuff concurrently. You can call [`wait()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.CmdChildren.html#method.wait) to wait
 //! for the process to finish.
+//! To check on it meanwhile, e.g. for a progress display,
+//! [`all_finished()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.CmdChildren.html#method.all_finished)
+//! and [`any_failed()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.CmdChildren.html#method.any_failed)
+//! poll its status without blocking.
 //!
 //! With [`spawn_with_output!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.spawn_with_output.html) you can get output by calling
 //! [`wait_with_output()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_with_output),
 //! [`wait_with_all()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_with_all)
 //! or even do stream
-//! processing with [`wait_with_pipe()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_with_pipe).
+//! processing with [`wait_with_pipe()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_with_pipe),
+//! or [`wait_with_pipes()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_with_pipes)
+//! to also process stderr lines as they are written.
 //!
 //! There are also other useful APIs, and you can check the docs for more details.
 //!
@@ -290,6 +696,24 @@
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
+//! To drive interactive programs, spawn them with
+//! [`spawn_with_stdin!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.spawn_with_stdin.html), and
+//! write to their stdin with [`take_stdin()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.take_stdin).
+//!
+//! To fail fast, [`wait_until_stderr_matches()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_until_stderr_matches)
+//! kills the pipeline on the first stderr line matching a predicate, or with the `regex` feature,
+//! a regular expression with [`wait_until_stderr_matches_regex()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_until_stderr_matches_regex):
+//! ```no_run
+//! # use cmd_lib::*;
+//! spawn_with_output!(cargo build)?.wait_until_stderr_matches(|line| line.starts_with("error:"))?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! With the `pty` feature on unix, [`run_cmd_pty!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.run_cmd_pty.html)
+//! and [`spawn_pty!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.spawn_pty.html) run a single
+//! command under a pseudo-terminal, capturing its combined stdout and stderr output, for tools
+//! which only print colors or progress bars to a terminal.
+//!
 //! ### Macro to register your own commands
 //! Declare your function with the right signature, and register it with [`use_custom_cmd!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.use_custom_cmd.html) macro:
 //!
@@ -313,6 +737,50 @@
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
+//! A custom command proxying to another tool can use
+//! [`wait_with_all_and_code()`](https://docs.rs/cmd_lib/latest/cmd_lib/struct.FunChildren.html#method.wait_with_all_and_code)
+//! to decide on the exit code of the tool, and propagate a specific code to its own caller with
+//! the builtin `exit-with` command:
+//!
+//! ```
+//! # use cmd_lib::*;
+//! # use std::io::Write;
+//! fn my_grep(env: &mut CmdEnv) -> CmdResult {
+//!     let args = env.get_args();
+//!     let (res, code, stdout, stderr) = spawn_with_output!(grep $[args])?.wait_with_all_and_code();
+//!     writeln!(env.stdout(), "{}", stdout)?;
+//!     match code {
+//!         // no match is not an error here
+//!         Some(0 | 1) => Ok(()),
+//!         Some(code) => {
+//!             writeln!(env.stderr(), "{}", stderr)?;
+//!             run_cmd!(exit-with $code)
+//!         }
+//!         None => res,
+//!     }
+//! }
+//!
+//! use_custom_cmd!(my_grep);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! For many commands, mark each function with the [`#[command]`](https://docs.rs/cmd_lib/latest/cmd_lib/attr.command.html)
+//! attribute instead, wherever it is defined, and register all of them at once with
+//! [`register_all_commands()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.register_all_commands.html):
+//!
+//! ```
+//! # use cmd_lib::*;
+//! # use std::io::Write;
+//! #[cmd_lib::command]
+//! fn greet(env: &mut CmdEnv) -> CmdResult {
+//!     writeln!(env.stdout(), "hello")
+//! }
+//!
+//! register_all_commands();
+//! assert_eq!(run_fun!(greet)?, "hello");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! ### Macros to define, get and set thread-local global variables
 //! - [`tls_init!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_init.html) to define thread local global variable
 //! - [`tls_get!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_get.html) to get the value
@@ -326,6 +794,16 @@
 //! // check more examples in examples/tetris.rs
 //! ```
 //!
+//! For config which is computed once and only read afterwards, use
+//! [`tls_init_shared!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_init_shared.html) and
+//! [`tls_get_shared!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_get_shared.html) instead,
+//! which share a single immutable value between all threads:
+//! ```
+//! # use cmd_lib::{ tls_init_shared, tls_get_shared };
+//! tls_init_shared!(FRAME_RATE, u32, 75);
+//! let delay = 1.0 / *tls_get_shared!(FRAME_RATE) as f64;
+//! ```
+//!
 //! ## Other Notes
 //!
 //! ### Environment Variables
@@ -345,6 +823,40 @@
 //! # Ok::<(), std::io::Error>(())
 //! ```
 //!
+//! A `HashMap` or `BTreeMap` vector variable expands to one assignment for each entry, with
+//! `$[env]`. As in bash, a later assignment of the same key wins:
+//! ```no_run
+//! # use cmd_lib::run_cmd;
+//! # use std::collections::HashMap;
+//! let env = HashMap::from([("FOO", "100"), ("BAR", "1")]);
+//! run_cmd!($[env] BAR=2 /tmp/test_run_cmd_lib.sh)?; // FOO=100 BAR=2
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! ### Program Aliases
+//!
+//! To run scripts across distributions, [`set_program_alias()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.set_program_alias.html)
+//! remaps a program name for all the following commands:
+//! ```no_run
+//! # use cmd_lib::*;
+//! set_program_alias("python", "python3");
+//! run_cmd!(python --version)?; // runs python3 --version
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! To only use a tool when it is available, check it with
+//! [`command_exists()`](https://docs.rs/cmd_lib/latest/cmd_lib/fn.command_exists.html) first,
+//! which looks up builtin and custom commands, and executables in `PATH`:
+//! ```no_run
+//! # use cmd_lib::*;
+//! if command_exists("pigz") {
+//!     run_cmd!(pigz data.tar)?;
+//! } else {
+//!     run_cmd!(gzip data.tar)?;
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
 //! ### Security Notes
 //! Using macros can actually avoid command injection, since we do parsing before variable substitution.
 //! For example, below code is fine even without any quotes:
@@ -366,29 +878,54 @@
 //!
 //! This library tries very hard to not set global states, so parallel `cargo test` can be executed just fine.
 //! The only known APIs not supported in multi-thread environment are the
-//! [`tls_init!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_init.html)/[`tls_get!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_get.html)/[`tls_set!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_set.html) macros, and you should only use them for *thread local* variables.
+//! [`tls_init!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_init.html)/[`tls_get!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_get.html)/[`tls_set!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_set.html) macros, and you should only use them for *thread local* variables. Shared variables from
+//! [`tls_init_shared!`](https://docs.rs/cmd_lib/latest/cmd_lib/macro.tls_init_shared.html) are
+//! safe to use from any thread, since they can not be changed.
 //!
 
 pub use cmd_lib_macros::{
-    cmd_die, main, run_cmd, run_fun, spawn, spawn_with_output, use_custom_cmd,
+    cmd_die, command, main, run_cmd, run_fun, run_fun_plain, run_fun_untrimmed, spawn,
+    spawn_with_output, spawn_with_stdin, use_custom_cmd,
 };
+#[cfg(feature = "pty")]
+pub use cmd_lib_macros::{run_cmd_pty, spawn_pty};
 /// Return type for [`run_fun!()`] macro.
 pub type FunResult = std::io::Result<String>;
 /// Return type for [`run_cmd!()`] macro.
 pub type CmdResult = std::io::Result<()>;
 pub use child::{CmdChildren, FunChildren};
+pub use cmd_str::{run_cmd_str, run_fun_str, SymTable};
+pub use env_logger::Builder as DefaultLoggerBuilder;
+#[doc(hidden)]
+pub use inventory;
 pub use io::{CmdIn, CmdOut};
 #[doc(hidden)]
 pub use log as inner_log;
+pub use logger::set_default_logger_builder;
 #[doc(hidden)]
 pub use logger::try_init_default_logger;
+pub use process::{
+    command_exists, debug_enabled, pipefail_enabled, register_all_commands, run_cmds, set_debug,
+    set_error_stderr_lines, set_inherit_stderr, set_noclobber, set_output_trim,
+    set_pipe_buffer_size, set_pipefail, set_program_alias, set_stderr_capture_limit,
+    set_strict_empty, set_xtrace, xtrace_enabled, CmdEnv, ScopedXtrace, TrimMode,
+};
+#[doc(hidden)]
+pub use process::{
+    register_cmd, AsOsStr, AsOsStrExt, Cmd, CmdString, Cmds, CustomCmd, GroupCmds, IntoOsArgs,
+    IntoOsArgsExt, Redirect, Scope,
+};
+pub use result::FunResultExt;
 #[doc(hidden)]
-pub use process::{register_cmd, AsOsStr, Cmd, CmdString, Cmds, GroupCmds, Redirect};
-pub use process::{set_debug, set_pipefail, CmdEnv};
+pub use thread_local::SharedVar;
 
 mod builtins;
 mod child;
+mod cmd_str;
 mod io;
 mod logger;
 mod process;
+#[cfg(feature = "pty")]
+mod pty;
+mod result;
 mod thread_local;
@@ -1,10 +1,59 @@
-use env_logger::Env;
+use env_logger::{Builder, Env, Target};
+use std::io::{self, ErrorKind, IsTerminal, Write};
+use std::sync::Mutex;
+
+type BuilderFn = fn(Builder) -> Builder;
+
+static DEFAULT_LOGGER_BUILDER: Mutex<Option<BuilderFn>> = Mutex::new(None);
+
+/// Customizes the default logger, which is installed on the first log message if no other logger
+/// is set, by adjusting its [`Builder`](env_logger::Builder) after the default settings.
+///
+/// It only takes effect if called before the default logger is installed.
+/// ```
+/// # use cmd_lib::*;
+/// set_default_logger_builder(|mut builder| {
+///     builder.format_timestamp_secs();
+///     builder
+/// });
+/// run_cmd!(info "with timestamp")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn set_default_logger_builder(f: fn(Builder) -> Builder) {
+    *DEFAULT_LOGGER_BUILDER.lock().unwrap() = Some(f);
+}
 
 pub fn try_init_default_logger() {
-    let _ = env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .format_target(false)
-        .format_timestamp(None)
-        .try_init();
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    builder.format_target(false).format_timestamp(None);
+    if !io::stderr().is_terminal() {
+        // only a pipe or a file can be closed on the reading side, and they get no colors anyway
+        builder.target(Target::Pipe(Box::new(StderrWriter)));
+    }
+    if let Some(f) = *DEFAULT_LOGGER_BUILDER.lock().unwrap() {
+        builder = f(builder);
+    }
+    let _ = builder.try_init();
+}
+
+// stderr of the default logger, dropping the messages once stderr is closed, like with
+// `myprog 2>&1 | head`, while commands keep running
+struct StderrWriter;
+
+impl Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match io::stderr().write(buf) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(buf.len()),
+            res => res,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match io::stderr().flush() {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+            res => res,
+        }
+    }
 }
 
 #[doc(hidden)]
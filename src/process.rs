@@ -1,23 +1,45 @@
 use crate::builtins::*;
 use crate::child::{CmdChild, CmdChildHandle, CmdChildren, FunChildren};
 use crate::io::{CmdIn, CmdOut};
-use crate::{debug, warn};
+use crate::result::strip_ansi;
+use crate::{debug, info, warn};
 use crate::{CmdResult, FunResult};
 use faccess::{AccessMode, PathExt};
 use lazy_static::lazy_static;
 use os_pipe::{self, PipeReader, PipeWriter};
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Mutex;
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 const CD_CMD: &str = "cd";
+const EXPORT_CMD: &str = "export";
 const IGNORE_CMD: &str = "ignore";
+const LABEL_CMD: &str = "label";
+const RAW_CMD: &str = "raw";
+const QUIET_STDERR_CMD: &str = "quiet_stderr";
+const QUIET_STDOUT_CMD: &str = "quiet_stdout";
+const SHOW_STDOUT_CMD: &str = "show_stdout";
+const SHOW_STDERR_CMD: &str = "show_stderr";
+const SWAP_OUT_ERR_CMD: &str = "swap_out_err";
+const DEFAULT_PIPE_BUFFER_SIZE: usize = 65536;
+
+thread_local! {
+    static CURRENT_LABEL: RefCell<Option<String>> = const { RefCell::new(None) };
+    static XTRACE: Cell<bool> = const { Cell::new(false) };
+}
+
+// current label set by builtin `label` command in this thread, used as prefix of logs
+pub(crate) fn current_label() -> Option<String> {
+    CURRENT_LABEL.with(|label| label.borrow().clone())
+}
 
 /// Environment for builtin or custom commands.
 pub struct CmdEnv {
@@ -27,6 +49,9 @@ pub struct CmdEnv {
     args: Vec<String>,
     vars: HashMap<String, String>,
     current_dir: PathBuf,
+    umask: Option<u32>,
+    file: String,
+    line: u32,
 }
 impl CmdEnv {
     /// Returns the name of this command.
@@ -44,11 +69,37 @@ impl CmdEnv {
         self.vars.get(key)
     }
 
+    /// Returns all the environment variables set inline for this command, like `FOO=1 cmd`, or by
+    /// a previous `export` in the same macro call.
+    pub fn vars(&self) -> &HashMap<String, String> {
+        &self.vars
+    }
+
+    /// Returns an iterator of the full environment of this command, which is the environment of
+    /// the current process merged with the variables set inline for this command.
+    pub fn env_iter(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        std::env::vars()
+            .filter(move |(k, _)| !self.vars.contains_key(k))
+            .chain(self.vars.iter().map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    /// Returns the source file and line of the macro invoking this command, to point errors back
+    /// at the caller.
+    pub fn caller_location(&self) -> (&str, u32) {
+        (&self.file, self.line)
+    }
+
     /// Returns the current working directory for this command.
     pub fn current_dir(&self) -> &Path {
         &self.current_dir
     }
 
+    /// Returns the file mode creation mask set by `umask` for this command, to apply to the
+    /// files it creates.
+    pub fn umask(&self) -> Option<u32> {
+        self.umask
+    }
+
     /// Returns a new handle to the standard input for this command.
     pub fn stdin(&mut self) -> &mut CmdIn {
         &mut self.stdin
@@ -72,6 +123,11 @@ lazy_static! {
         // needs explicit type, or it won't compile
         let mut m: HashMap<OsString, FnFun> = HashMap::new();
         m.insert("echo".into(), builtin_echo);
+        m.insert("mkdir".into(), builtin_mkdir);
+        m.insert("tee".into(), builtin_tee);
+        m.insert("cmp".into(), builtin_cmp);
+        m.insert("touch".into(), builtin_touch);
+        m.insert("exit-with".into(), builtin_exit_with);
         m.insert("trace".into(), builtin_trace);
         m.insert("debug".into(), builtin_debug);
         m.insert("info".into(), builtin_info);
@@ -81,15 +137,140 @@ lazy_static! {
 
         Mutex::new(m)
     };
+    static ref PROGRAM_ALIASES: Mutex<HashMap<OsString, OsString>> = Mutex::new(HashMap::new());
+    // builtins replacing system programs, with the options they implement, which run the
+    // program instead for any other option
+    static ref PROGRAM_BUILTINS: Mutex<HashMap<OsString, &'static [&'static str]>> = {
+        let mut m: HashMap<OsString, &'static [&'static str]> = HashMap::new();
+        m.insert("mkdir".into(), &["-p"]);
+        m.insert("tee".into(), &["-a"]);
+        m.insert("touch".into(), &["-c"]);
+        m.insert("cmp".into(), &["-s"]);
+        Mutex::new(m)
+    };
 }
 
 #[doc(hidden)]
 pub fn register_cmd(cmd: &'static str, func: FnFun) {
+    // a custom command takes over all the options
+    PROGRAM_BUILTINS.lock().unwrap().remove(OsStr::new(cmd));
     CMD_MAP.lock().unwrap().insert(OsString::from(cmd), func);
 }
 
+// whether a builtin replacing a system program implements all the options in `args`, like
+// `mkdir -p`, or leaves them to the program, like `mkdir -m 700`
+fn builtin_handles(args: &[OsString]) -> bool {
+    let programs = PROGRAM_BUILTINS.lock().unwrap();
+    match args.first().and_then(|cmd| programs.get(cmd)) {
+        Some(options) => args[1..].iter().all(|arg| {
+            !arg.to_string_lossy().starts_with('-') || options.iter().any(|opt| arg == *opt)
+        }),
+        None => true,
+    }
+}
+
+// a custom command submitted by the `#[cmd_lib::command]` attribute
+#[doc(hidden)]
+pub struct CustomCmd {
+    pub name: &'static str,
+    pub func: FnFun,
+}
+inventory::collect!(CustomCmd);
+
+/// Registers all the custom commands marked with the [`#[command]`](crate::command) attribute,
+/// from any module of the program, like listing each of them in
+/// [`use_custom_cmd!`](crate::use_custom_cmd).
+pub fn register_all_commands() {
+    for cmd in inventory::iter::<CustomCmd> {
+        register_cmd(cmd.name, cmd.func);
+    }
+}
+
+/// Runs each of `cmds` in order as a separate statement, like a group of `run_cmd!`, stopping at
+/// the first error.
+///
+/// Each command is a program followed by its arguments, without any parsing or interpolation,
+/// for batches of commands built at runtime. Like in macros, a command can still start with
+/// `ignore` or `FOO=bar` style environment variables.
+/// ```no_run
+/// # use cmd_lib::run_cmds;
+/// let cmds = vec![vec!["mkdir", "-p", "/tmp/batch"], vec!["touch", "/tmp/batch/a b"]];
+/// run_cmds(&cmds)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[track_caller]
+pub fn run_cmds<S: AsRef<OsStr>>(cmds: &[Vec<S>]) -> CmdResult {
+    let location = std::panic::Location::caller();
+    let mut group = GroupCmds::default();
+    for args in cmds {
+        if args.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("empty command at {}:{}", location.file(), location.line()),
+            ));
+        }
+        let cmd = Cmd::default()
+            .with_location(location.file(), location.line())
+            .add_args(args);
+        group = group.append(Cmds::default().pipe(cmd));
+    }
+    group.run_cmd()
+}
+
+/// Run `target` whenever `program` is invoked as an external command, like `python` as
+/// `python3`, to keep scripts portable without editing every call.
+///
+/// Builtin and custom commands are not affected.
+pub fn set_program_alias(program: &str, target: &str) {
+    PROGRAM_ALIASES
+        .lock()
+        .unwrap()
+        .insert(program.into(), target.into());
+}
+
+/// Check whether `name` can be run as a command, either as a builtin or custom command,
+/// or as an executable found in `PATH`, like `command -v` in bash.
+///
+/// Names containing a path separator are checked directly, without searching `PATH`.
+/// ```
+/// # use cmd_lib::*;
+/// assert!(command_exists("echo"));
+/// assert!(!command_exists("no_such_command_here"));
+/// ```
+pub fn command_exists(name: &str) -> bool {
+    if CMD_MAP.lock().unwrap().contains_key(OsStr::new(name)) {
+        return true;
+    }
+    let program = PROGRAM_ALIASES
+        .lock()
+        .unwrap()
+        .get(OsStr::new(name))
+        .cloned()
+        .unwrap_or_else(|| name.into());
+    let program = Path::new(&program);
+    if program.components().count() > 1 {
+        return is_executable_file(program);
+    }
+    std::env::var_os("PATH").is_some_and(|paths| find_in_paths(program, &paths))
+}
+
+fn find_in_paths(program: &Path, paths: &OsStr) -> bool {
+    std::env::split_paths(paths).any(|dir| {
+        let path = dir.join(program);
+        is_executable_file(&path) || (cfg!(windows) && path.with_extension("exe").is_file())
+    })
+}
+
+fn is_executable_file(path: &Path) -> bool {
+    path.is_file() && path.executable()
+}
+
 /// Set debug mode or not, false by default.
 ///
+/// Besides logging each command before it runs, on linux it warns about the output of
+/// [`spawn_with_output!`](crate::spawn_with_output) left unread for a few seconds, while the
+/// children block on a full pipe.
+///
 /// Setting environment variable CMD_LIB_DEBUG=0|1 has the same effect
 pub fn set_debug(enable: bool) {
     std::env::set_var("CMD_LIB_DEBUG", if enable { "1" } else { "0" });
@@ -102,58 +283,525 @@ pub fn set_pipefail(enable: bool) {
     std::env::set_var("CMD_LIB_PIPEFAIL", if enable { "1" } else { "0" });
 }
 
-pub(crate) fn debug_enabled() -> bool {
+/// Set stderr to be inherited from the parent process instead of being logged, false by default.
+///
+/// Setting environment variable CMD_LIB_INHERIT_STDERR=0|1 has the same effect
+pub fn set_inherit_stderr(enable: bool) {
+    std::env::set_var("CMD_LIB_INHERIT_STDERR", if enable { "1" } else { "0" });
+}
+
+/// Set the maximum bytes of stderr captured by [`wait_with_all()`](crate::FunChildren::wait_with_all),
+/// no limit by default.
+///
+/// Further stderr output is dropped with a truncation marker once the limit is reached.
+/// Setting environment variable CMD_LIB_STDERR_CAPTURE_LIMIT=<bytes> has the same effect
+pub fn set_stderr_capture_limit(limit: Option<usize>) {
+    if let Some(limit) = limit {
+        std::env::set_var("CMD_LIB_STDERR_CAPTURE_LIMIT", limit.to_string());
+    } else {
+        std::env::remove_var("CMD_LIB_STDERR_CAPTURE_LIMIT");
+    }
+}
+
+/// Set the number of trailing stderr lines of a failed command to include in its error, 0 by
+/// default.
+///
+/// The lines are still logged as usual. Setting environment variable
+/// CMD_LIB_ERROR_STDERR_LINES=<lines> has the same effect
+pub fn set_error_stderr_lines(lines: usize) {
+    std::env::set_var("CMD_LIB_ERROR_STDERR_LINES", lines.to_string());
+}
+
+/// Set noclobber mode or not, false by default.
+///
+/// In noclobber mode, redirections with `>` fail instead of overwriting existing files, like
+/// `set -o noclobber` in bash. Use `>|` to overwrite them anyway.
+/// Setting environment variable CMD_LIB_NOCLOBBER=0|1 has the same effect
+/// ```
+/// # use cmd_lib::*;
+/// let stamp = std::env::temp_dir().join("cmd_lib_noclobber");
+/// run_cmd!(date > $stamp)?;
+/// set_noclobber(true);
+/// assert!(run_cmd!(date > $stamp).is_err());
+/// run_cmd!(date >> $stamp; date >| $stamp; echo ok > /dev/null)?;
+/// # std::fs::remove_file(stamp)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn set_noclobber(enable: bool) {
+    std::env::set_var("CMD_LIB_NOCLOBBER", if enable { "1" } else { "0" });
+}
+
+/// Set strict empty mode or not, false by default.
+///
+/// In strict empty mode, a command without any program after variable substitution fails,
+/// instead of silently doing nothing, to catch variables which are unexpectedly empty.
+/// Setting environment variable CMD_LIB_STRICT_EMPTY=0|1 has the same effect
+/// ```
+/// # use cmd_lib::*;
+/// let cmd = "";
+/// assert!(run_cmd!($cmd).is_ok());
+/// set_strict_empty(true);
+/// assert!(run_cmd!($cmd).is_err());
+/// assert!(run_cmd!(echo $cmd).is_ok());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn set_strict_empty(enable: bool) {
+    std::env::set_var("CMD_LIB_STRICT_EMPTY", if enable { "1" } else { "0" });
+}
+
+/// Set the buffer size in bytes used to read stdout in
+/// [`wait_with_pipe()`](crate::FunChildren::wait_with_pipe) and
+/// [`wait_discarding_output()`](crate::CmdChildren::wait_discarding_output), 65536 by default.
+///
+/// Smaller buffers suit low latency line streaming, and larger ones suit throughput.
+/// Setting environment variable CMD_LIB_PIPE_BUFFER_SIZE=<bytes> has the same effect
+pub fn set_pipe_buffer_size(size: usize) {
+    std::env::set_var("CMD_LIB_PIPE_BUFFER_SIZE", size.to_string());
+}
+
+/// How the captured output of [`run_fun!`](crate::run_fun) and the `wait_with_*` methods of
+/// [`FunChildren`] is trimmed, [`TrimMode::TrailingNewline`] by default.
+///
+/// Set it globally with [`set_output_trim()`], or per call with
+/// [`wait_with_output_trimmed()`](crate::FunChildren::wait_with_output_trimmed) and
+/// [`apply()`](TrimMode::apply) on untrimmed output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrimMode {
+    /// Keep the output as is, like [`run_fun_untrimmed!`](crate::run_fun_untrimmed).
+    None,
+    /// Remove one trailing `\n`, like `$(...)` in bash removing the last line ending.
+    #[default]
+    TrailingNewline,
+    /// Remove one trailing `\n` or `\r\n`, for commands printing windows line endings.
+    Crlf,
+    /// Remove all trailing whitespace.
+    All,
+}
+
+impl TrimMode {
+    /// Trims `output` in place according to the mode.
+    /// ```
+    /// # use cmd_lib::TrimMode;
+    /// let mut output = String::from("a b \r\n\n");
+    /// TrimMode::Crlf.apply(&mut output);
+    /// assert_eq!(output, "a b \r\n");
+    /// TrimMode::All.apply(&mut output);
+    /// assert_eq!(output, "a b");
+    /// ```
+    pub fn apply(self, output: &mut String) {
+        match self {
+            TrimMode::None => {}
+            TrimMode::TrailingNewline => {
+                if output.ends_with('\n') {
+                    output.pop();
+                }
+            }
+            TrimMode::Crlf => {
+                if output.ends_with('\n') {
+                    output.pop();
+                    if output.ends_with('\r') {
+                        output.pop();
+                    }
+                }
+            }
+            TrimMode::All => output.truncate(output.trim_end().len()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TrimMode::None => "none",
+            TrimMode::TrailingNewline => "newline",
+            TrimMode::Crlf => "crlf",
+            TrimMode::All => "all",
+        }
+    }
+}
+
+/// Set how captured output is trimmed, [`TrimMode::TrailingNewline`] by default.
+///
+/// It applies to all trimmed captures, while [`run_fun_untrimmed!`](crate::run_fun_untrimmed)
+/// keeps the output as is.
+/// Setting environment variable CMD_LIB_OUTPUT_TRIM=none|newline|crlf|all has the same effect
+/// ```
+/// # use cmd_lib::*;
+/// set_output_trim(TrimMode::All);
+/// assert_eq!(run_fun!(printf "hello \\n\\n")?, "hello");
+/// set_output_trim(TrimMode::None);
+/// assert_eq!(run_fun!(echo hello)?, "hello\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn set_output_trim(mode: TrimMode) {
+    std::env::set_var("CMD_LIB_OUTPUT_TRIM", mode.name());
+}
+
+/// Set xtrace mode or not in the current thread, false by default.
+///
+/// In xtrace mode, each command is logged with a `+ ` prefix and its resolved arguments right
+/// before it is spawned, like `bash -x`.
+pub fn set_xtrace(enable: bool) {
+    XTRACE.with(|xtrace| xtrace.set(enable));
+}
+
+/// Returns whether xtrace mode is enabled in the current thread, by [`set_xtrace()`] or
+/// [`ScopedXtrace`].
+pub fn xtrace_enabled() -> bool {
+    XTRACE.with(|xtrace| xtrace.get())
+}
+
+/// Guard to set xtrace mode in the current thread, which restores the previous mode when dropped.
+///
+/// ```no_run
+/// # use cmd_lib::{run_cmd, ScopedXtrace};
+/// {
+///     let _xtrace = ScopedXtrace::new(true);
+///     run_cmd!(ls | wc -l)?; // logs "+ ls" and "+ wc -l"
+/// }
+/// run_cmd!(ls)?; // not logged
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct ScopedXtrace {
+    prev: bool,
+}
+
+impl ScopedXtrace {
+    pub fn new(enable: bool) -> Self {
+        let prev = xtrace_enabled();
+        set_xtrace(enable);
+        Self { prev }
+    }
+}
+
+impl Drop for ScopedXtrace {
+    fn drop(&mut self) {
+        set_xtrace(self.prev);
+    }
+}
+
+/// Returns whether debug mode is enabled, by [`set_debug()`] or CMD_LIB_DEBUG.
+pub fn debug_enabled() -> bool {
     std::env::var("CMD_LIB_DEBUG") == Ok("1".into())
 }
 
-pub(crate) fn pipefail_enabled() -> bool {
+/// Returns whether pipefail is enabled, by [`set_pipefail()`] or CMD_LIB_PIPEFAIL.
+pub fn pipefail_enabled() -> bool {
     std::env::var("CMD_LIB_PIPEFAIL") != Ok("0".into())
 }
 
+pub(crate) fn stderr_capture_limit() -> Option<usize> {
+    std::env::var("CMD_LIB_STDERR_CAPTURE_LIMIT")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+}
+
+pub(crate) fn error_stderr_lines() -> usize {
+    std::env::var("CMD_LIB_ERROR_STDERR_LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+        .unwrap_or(0)
+}
+
+pub(crate) fn pipe_buffer_size() -> usize {
+    std::env::var("CMD_LIB_PIPE_BUFFER_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_PIPE_BUFFER_SIZE)
+}
+
+pub(crate) fn output_trim() -> TrimMode {
+    match std::env::var("CMD_LIB_OUTPUT_TRIM").as_deref() {
+        Ok("none") => TrimMode::None,
+        Ok("crlf") => TrimMode::Crlf,
+        Ok("all") => TrimMode::All,
+        _ => TrimMode::TrailingNewline,
+    }
+}
+
+pub(crate) fn noclobber_enabled() -> bool {
+    std::env::var("CMD_LIB_NOCLOBBER") == Ok("1".into())
+}
+
+pub(crate) fn strict_empty_enabled() -> bool {
+    std::env::var("CMD_LIB_STRICT_EMPTY") == Ok("1".into())
+}
+
+pub(crate) fn inherit_stderr_enabled() -> bool {
+    std::env::var("CMD_LIB_INHERIT_STDERR") == Ok("1".into())
+}
+
+// a statement with command substitutions, built right before it runs
+type BuildCmds<'a> = Box<dyn FnOnce(&Scope) -> Result<Cmds> + 'a>;
+
+/// The current directory and exported variables of a running group, which the command
+/// substitutions of its next statement run in.
 #[doc(hidden)]
 #[derive(Default)]
-pub struct GroupCmds {
+pub struct Scope {
+    current_dir: PathBuf,
+    exports: BTreeMap<String, String>,
+}
+
+#[doc(hidden)]
+#[derive(Default)]
+pub struct GroupCmds<'a> {
     group_cmds: Vec<Cmds>,
+    // builders of the statements with command substitutions, by statement index
+    builders: Vec<Option<BuildCmds<'a>>>,
     current_dir: PathBuf,
+    exports: BTreeMap<String, String>,
+    share_stdin: bool,
+    // thread feeding the stdin shared by all the statements
+    stdin_copy: Option<JoinHandle<CmdResult>>,
+    timeout: Option<Duration>,
+    error: Option<Error>,
 }
 
-impl GroupCmds {
+impl<'a> GroupCmds<'a> {
     pub fn append(mut self, cmds: Cmds) -> Self {
         self.group_cmds.push(cmds);
+        self.builders.push(None);
         self
     }
 
-    pub fn run_cmd(&mut self) -> CmdResult {
-        for cmds in self.group_cmds.iter_mut() {
-            if let Err(e) = cmds.run_cmd(&mut self.current_dir) {
-                if !cmds.ignore_error {
-                    return Err(e);
-                }
-            }
+    /// Appends a statement with command substitutions, which are run only when the statement
+    /// itself runs, after the previous statements.
+    pub fn append_lazy<F: FnOnce(&Scope) -> Result<Cmds> + 'a>(mut self, build: F) -> Self {
+        self.group_cmds.push(Cmds::default());
+        self.builders.push(Some(Box::new(build)));
+        self
+    }
+
+    /// Runs the commands in the current directory and with the exported variables of `scope`,
+    /// for command substitutions.
+    pub fn in_scope(mut self, scope: &Scope) -> Self {
+        self.current_dir = scope.current_dir.clone();
+        self.exports = scope.exports.clone();
+        self
+    }
+
+    pub fn share_stdin(mut self, share: bool) -> Self {
+        self.share_stdin = share;
+        self
+    }
+
+    /// Kills the running statement, and fails with [`ErrorKind::TimedOut`], once all the
+    /// statements together run longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the resolved command string of the statements that would run, joined with "; ",
+    /// without running them, for previews and audit logs.
+    ///
+    /// Command substitutions are still run to resolve the arguments, all in the starting
+    /// directory, and their errors are returned.
+    pub fn to_command_string(&mut self) -> Result<String> {
+        for i in 0..self.group_cmds.len() {
+            self.build_statement(i)?;
         }
-        Ok(())
+        Ok(self
+            .group_cmds
+            .iter()
+            .filter(|cmds| !cmds.skipped)
+            .map(|cmds| cmds.full_cmds.as_str())
+            .collect::<Vec<_>>()
+            .join("; "))
+    }
+
+    pub fn run_cmd(&mut self) -> CmdResult {
+        self.take_error()?;
+        let total = self.group_cmds.len();
+        self.run_group_cmds(total, total, self.deadline())?;
+        self.join_stdin_copy()
     }
 
     pub fn run_fun(&mut self) -> FunResult {
+        self.inner_run_fun(true)
+    }
+
+    pub fn run_fun_untrimmed(&mut self) -> FunResult {
+        self.inner_run_fun(false)
+    }
+
+    pub fn run_fun_plain(&mut self) -> FunResult {
+        // strip before trimming, since a reset sequence may follow the trailing newline
+        let mut output = strip_ansi(&self.inner_run_fun(false)?);
+        output_trim().apply(&mut output);
+        Ok(output)
+    }
+
+    fn inner_run_fun(&mut self, trim: bool) -> FunResult {
+        let output = self.run_fun_statements(trim)?;
+        self.join_stdin_copy()?;
+        Ok(output)
+    }
+
+    fn run_fun_statements(&mut self, trim: bool) -> FunResult {
+        self.take_error()?;
+        let total = self.group_cmds.len();
         // run previous commands
-        let mut last_cmd = self.group_cmds.pop().unwrap();
-        self.run_cmd()?;
+        let deadline = self.deadline();
+        self.run_group_cmds(total - 1, total, deadline)?;
         // run last function command
-        let ret = last_cmd.run_fun(&mut self.current_dir);
+        self.build_statement(total - 1)
+            .map_err(|e| Self::statement_error(e, total, total))?;
+        let mut last_cmd = self.group_cmds.pop().unwrap();
+        last_cmd.add_exports(&self.exports);
+        let ret = match last_cmd.run_export(&mut self.exports) {
+            Some(res) => res.map(|_| String::new()),
+            None => last_cmd.run_fun_until(&mut self.current_dir, deadline, trim),
+        };
         if ret.is_err() && last_cmd.ignore_error {
+            // output of a failed command is already kept, only spawning errors get here
             return Ok("".into());
         }
-        ret
+        ret.map_err(|e| Self::statement_error(e, total, total))
+    }
+
+    // error from setting up the commands, such as a failed stdin pipe
+    fn take_error(&mut self) -> CmdResult {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // the deadline of all the statements, with the timeout it was set from
+    fn deadline(&self) -> Option<(Instant, Duration)> {
+        self.timeout
+            .map(|timeout| (Instant::now() + timeout, timeout))
+    }
+
+    // builds statement `i` if it has command substitutions, which run in the current directory
+    // and with the variables exported by the previous statements
+    fn build_statement(&mut self, i: usize) -> CmdResult {
+        if let Some(build) = self.builders[i].take() {
+            let scope = Scope {
+                current_dir: self.current_dir.clone(),
+                exports: self.exports.clone(),
+            };
+            let mut cmds = build(&scope)?;
+            // keep the stdin set up before building
+            let pending = &mut self.group_cmds[i];
+            cmds.stdin_reader = pending.stdin_reader.take();
+            cmds.stdin_pipe = pending.stdin_pipe.take();
+            *pending = cmds;
+        }
+        Ok(())
+    }
+
+    fn run_group_cmds(
+        &mut self,
+        count: usize,
+        total: usize,
+        deadline: Option<(Instant, Duration)>,
+    ) -> CmdResult {
+        for i in 0..count {
+            self.build_statement(i)
+                .map_err(|e| Self::statement_error(e, i + 1, total))?;
+            let cmds = &mut self.group_cmds[i];
+            cmds.add_exports(&self.exports);
+            let res = match cmds.run_export(&mut self.exports) {
+                Some(res) => res,
+                None => cmds.run_cmd_until(&mut self.current_dir, deadline),
+            };
+            if let Err(e) = res {
+                if !cmds.ignore_error {
+                    return Err(Self::statement_error(e, i + 1, total));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // tell which statement failed, if there are more than one in the group
+    fn statement_error(e: Error, index: usize, total: usize) -> Error {
+        if total <= 1 {
+            return e;
+        }
+        rewrap_error(&e, format!("statement {index}/{total}: {e}"))
+    }
+
+    pub fn run_cmd_with_reader(&mut self, r: Box<dyn Read + Send>) -> CmdResult {
+        self.set_stdin_reader(r);
+        self.run_cmd()
+    }
+
+    fn set_stdin_reader(&mut self, r: Box<dyn Read + Send>) {
+        if self.share_stdin && self.group_cmds.len() > 1 {
+            if let Err(e) = self.set_shared_stdin_reader(r) {
+                self.error.get_or_insert(e);
+            }
+        } else if let Some(cmds) = self.group_cmds.first_mut() {
+            cmds.stdin_reader = Some(r);
+        }
+    }
+
+    // feed one pipe from the reader, with its read end shared by all the statements
+    fn set_shared_stdin_reader(&mut self, r: Box<dyn Read + Send>) -> CmdResult {
+        let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
+        for cmds in self.group_cmds.iter_mut() {
+            cmds.stdin_pipe = Some(pipe_reader.try_clone()?);
+        }
+        self.stdin_copy = Some(spawn_stdin_copy(r, pipe_writer)?);
+        Ok(())
+    }
+
+    // waits for the thread feeding the shared stdin, once all the statements succeeded, and
+    // returns its error
+    fn join_stdin_copy(&mut self) -> CmdResult {
+        // close the pipe kept for statements which did not run, like the skipped ones
+        for cmds in self.group_cmds.iter_mut() {
+            cmds.stdin_pipe = None;
+        }
+        match self.stdin_copy.take().map(JoinHandle::join) {
+            Some(Ok(res)) => res,
+            Some(Err(e)) => Err(Error::other(format!(
+                "Copying stdin thread joined with error: {e:?}"
+            ))),
+            None => Ok(()),
+        }
     }
 
     pub fn spawn(mut self, with_output: bool) -> Result<CmdChildren> {
+        self.take_error()?;
         assert_eq!(self.group_cmds.len(), 1);
+        self.build_statement(0)?;
         let mut cmds = self.group_cmds.pop().unwrap();
         cmds.spawn(&mut self.current_dir, with_output)
     }
 
     pub fn spawn_with_output(self) -> Result<FunChildren> {
-        self.spawn(true).map(CmdChildren::into_fun_children)
+        let children = self.spawn(true).map(CmdChildren::into_fun_children)?;
+        if debug_enabled() {
+            return Ok(children.with_stdout_watchdog());
+        }
+        Ok(children)
+    }
+
+    pub fn spawn_with_stdin(mut self) -> Result<FunChildren> {
+        let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
+        if let Some(cmds) = self.group_cmds.first_mut() {
+            cmds.stdin_pipe = Some(pipe_reader);
+        }
+        self.spawn_with_output()
+            .map(|children| children.with_stdin(pipe_writer))
+    }
+
+    #[cfg(feature = "pty")]
+    pub fn spawn_pty(mut self) -> Result<FunChildren> {
+        self.take_error()?;
+        assert_eq!(self.group_cmds.len(), 1);
+        self.build_statement(0)?;
+        self.group_cmds[0].set_pty()?;
+        self.spawn_with_output()
+    }
+
+    #[cfg(feature = "pty")]
+    pub fn run_pty(self) -> FunResult {
+        self.spawn_pty()?.wait_with_output()
     }
 }
 
@@ -163,11 +811,22 @@ pub struct Cmds {
     cmds: Vec<Option<Cmd>>,
     full_cmds: String,
     ignore_error: bool,
+    skipped: bool,
     file: String,
     line: u32,
+    stdin_reader: Option<Box<dyn Read + Send>>,
+    stdin_pipe: Option<PipeReader>,
 }
 
 impl Cmds {
+    /// A statement skipped by a false `when` condition, without building its commands.
+    pub fn skipped() -> Self {
+        Self {
+            skipped: true,
+            ..Default::default()
+        }
+    }
+
     pub fn pipe(mut self, cmd: Cmd) -> Self {
         if self.full_cmds.is_empty() {
             self.file = cmd.file.clone();
@@ -176,6 +835,9 @@ impl Cmds {
             self.full_cmds += " | ";
         }
         self.full_cmds += &cmd.cmd_str();
+        if !cmd.enabled {
+            self.skipped = true;
+        }
         let (ignore_error, cmd) = cmd.gen_command();
         if ignore_error {
             if self.cmds.is_empty() {
@@ -192,20 +854,103 @@ impl Cmds {
         self
     }
 
+    #[cfg(feature = "pty")]
+    fn set_pty(&mut self) -> CmdResult {
+        if self.cmds.len() != 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Running [{}] failed: pty only supports a single command at {}:{}",
+                    self.full_cmds, self.file, self.line
+                ),
+            ));
+        }
+        if let Some(cmd) = self.cmds[0].as_mut() {
+            cmd.pty = true;
+        }
+        Ok(())
+    }
+
+    // pass the variables exported by previous statements, unless set inline
+    fn add_exports(&mut self, exports: &BTreeMap<String, String>) {
+        for cmd in self.cmds.iter_mut().flatten() {
+            cmd.add_exports(exports);
+        }
+    }
+
+    // a single `export` statement records its assignments for the following statements in the
+    // group, instead of spawning anything
+    fn run_export(&self, exports: &mut BTreeMap<String, String>) -> Option<CmdResult> {
+        let cmd = match self.cmds.as_slice() {
+            [Some(cmd)] if !self.skipped && cmd.arg0() == EXPORT_CMD => cmd,
+            _ => return None,
+        };
+        let mut vars = vec![];
+        for arg in cmd.args.iter().skip_while(|arg| *arg == IGNORE_CMD).skip(1) {
+            let arg = arg.to_string_lossy();
+            match arg.split_once('=') {
+                Some((key, value))
+                    if !key.is_empty()
+                        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                {
+                    vars.push((key.to_string(), value.to_string()));
+                }
+                _ => {
+                    let err_msg = format!(
+                        "{EXPORT_CMD}: expect KEY=VALUE, got {arg:?} at {}:{}",
+                        self.file, self.line
+                    );
+                    return Some(Err(Error::new(ErrorKind::InvalidInput, err_msg)));
+                }
+            }
+        }
+        exports.extend(vars);
+        Some(Ok(()))
+    }
+
     fn spawn(&mut self, current_dir: &mut PathBuf, with_output: bool) -> Result<CmdChildren> {
         let full_cmds = self.full_cmds.clone();
         let file = self.file.clone();
         let line = self.line;
+        if self.skipped {
+            // condition is false, nothing to run or to output
+            let child = CmdChild::new(CmdChildHandle::SyncFn, full_cmds, file, line, None, None);
+            return Ok(CmdChildren::new(vec![child], self.ignore_error));
+        }
         if debug_enabled() {
-            debug!("Running [{full_cmds}] at {file}:{line} ...");
+            if let Some(label) = current_label() {
+                debug!("[{label}] Running [{full_cmds}] at {file}:{line} ...");
+            } else {
+                debug!("Running [{full_cmds}] at {file}:{line} ...");
+            }
         }
 
         // spawning all the sub-processes
         let mut children: Vec<CmdChild> = Vec::new();
         let len = self.cmds.len();
         let mut prev_pipe_in = None;
+        if let Some(pipe) = self.stdin_pipe.take() {
+            prev_pipe_in = Some(pipe);
+        } else if let Some(reader) = self.stdin_reader.take() {
+            // feed the first command's stdin from the reader in a copy thread, which is waited
+            // like a command at the head of the pipe
+            let (pipe_reader, pipe_writer) =
+                os_pipe::pipe().map_err(|e| new_cmd_io_error(&e, &full_cmds, &file, line))?;
+            let copy = spawn_stdin_copy(reader, pipe_writer)
+                .map_err(|e| new_cmd_io_error(&e, &full_cmds, &file, line))?;
+            children.push(CmdChild::new(
+                CmdChildHandle::Thread(copy),
+                full_cmds.clone(),
+                file.clone(),
+                line,
+                None,
+                None,
+            ));
+            prev_pipe_in = Some(pipe_reader);
+        }
         for (i, cmd_opt) in self.cmds.iter_mut().enumerate() {
             let mut cmd = cmd_opt.take().unwrap();
+            cmd.ignore_error = self.ignore_error;
             if i != len - 1 {
                 // not the last, update redirects
                 let (pipe_reader, pipe_writer) =
@@ -232,42 +977,87 @@ impl Cmds {
     }
 
     fn run_cmd(&mut self, current_dir: &mut PathBuf) -> CmdResult {
-        self.spawn(current_dir, false)?.wait()
+        self.run_cmd_until(current_dir, None)
+    }
+
+    fn run_cmd_until(
+        &mut self,
+        current_dir: &mut PathBuf,
+        deadline: Option<(Instant, Duration)>,
+    ) -> CmdResult {
+        let mut children = self.spawn(current_dir, false)?;
+        match deadline {
+            Some((deadline, timeout)) => {
+                children.wait_deadline_with(deadline, &format!("timed out after {timeout:?}"))
+            }
+            None => children.wait(),
+        }
     }
 
-    fn run_fun(&mut self, current_dir: &mut PathBuf) -> FunResult {
-        self.spawn_with_output(current_dir)?.wait_with_output()
+    pub fn run_cmd_with_reader(&mut self, r: Box<dyn Read + Send>) -> CmdResult {
+        self.stdin_reader = Some(r);
+        self.run_cmd(&mut PathBuf::new())
+    }
+
+    fn run_fun_until(
+        &mut self,
+        current_dir: &mut PathBuf,
+        deadline: Option<(Instant, Duration)>,
+        trim: bool,
+    ) -> FunResult {
+        let mut children = self.spawn_with_output(current_dir)?;
+        match deadline {
+            Some((deadline, timeout)) => children.wait_with_output_until(
+                deadline,
+                &format!("timed out after {timeout:?}"),
+                trim,
+            ),
+            None if trim => children.wait_with_output(),
+            None => children.wait_with_untrimmed_output(),
+        }
     }
 }
 
 #[doc(hidden)]
 pub enum Redirect {
     FileToStdin(PathBuf),
+    HereDoc(OsString),
     StdoutToStderr,
     StderrToStdout,
-    StdoutToFile(PathBuf, bool),
-    StderrToFile(PathBuf, bool),
+    StdoutToFile(PathBuf, bool, bool),
+    StderrToFile(PathBuf, bool, bool),
+    OpenFileToStdin(File),
+    StdoutToOpenFile(File),
+    StderrToOpenFile(File),
 }
 impl fmt::Debug for Redirect {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Redirect::FileToStdin(path) => f.write_str(&format!("<{:?}", path.display())),
+            Redirect::HereDoc(content) => f.write_str(&format!("<<{content:?}")),
             Redirect::StdoutToStderr => f.write_str(">&2"),
             Redirect::StderrToStdout => f.write_str("2>&1"),
-            Redirect::StdoutToFile(path, append) => {
+            Redirect::StdoutToFile(path, append, force) => {
                 if *append {
                     f.write_str(&format!("1>>{:?}", path.display()))
+                } else if *force {
+                    f.write_str(&format!("1>|{:?}", path.display()))
                 } else {
                     f.write_str(&format!("1>{:?}", path.display()))
                 }
             }
-            Redirect::StderrToFile(path, append) => {
+            Redirect::StderrToFile(path, append, force) => {
                 if *append {
                     f.write_str(&format!("2>>{:?}", path.display()))
+                } else if *force {
+                    f.write_str(&format!("2>|{:?}", path.display()))
                 } else {
                     f.write_str(&format!("2>{:?}", path.display()))
                 }
             }
+            Redirect::OpenFileToStdin(file) => f.write_str(&format!("<{file:?}")),
+            Redirect::StdoutToOpenFile(file) => f.write_str(&format!("1>{file:?}")),
+            Redirect::StderrToOpenFile(file) => f.write_str(&format!("2>{file:?}")),
         }
     }
 }
@@ -279,6 +1069,17 @@ pub struct Cmd {
     args: Vec<OsString>,
     vars: HashMap<String, String>,
     redirects: Vec<Redirect>,
+    inherit_stdout: bool,
+    inherit_stderr: bool,
+    raw: bool,
+    quiet_stderr: bool,
+    quiet_stdout: bool,
+    swap_out_err: bool,
+    pty: bool,
+    enabled: bool,
+    umask: Option<u32>,
+    current_dir: Option<PathBuf>,
+    label: Option<String>,
     file: String,
     line: u32,
 
@@ -289,6 +1090,7 @@ pub struct Cmd {
     stderr_redirect: Option<CmdOut>,
     stdout_logging: Option<PipeReader>,
     stderr_logging: Option<PipeReader>,
+    ignore_error: bool, // set from the pipe, whose errors are ignored
 }
 
 impl Default for Cmd {
@@ -298,6 +1100,17 @@ impl Default for Cmd {
             args: vec![],
             vars: HashMap::new(),
             redirects: vec![],
+            inherit_stdout: false,
+            inherit_stderr: false,
+            raw: false,
+            quiet_stderr: false,
+            quiet_stdout: false,
+            swap_out_err: false,
+            pty: false,
+            enabled: true,
+            umask: None,
+            current_dir: None,
+            label: None,
             file: "".into(),
             line: 0,
             std_cmd: None,
@@ -306,6 +1119,7 @@ impl Default for Cmd {
             stderr_redirect: None,
             stdout_logging: None,
             stderr_logging: None,
+            ignore_error: false,
         }
     }
 }
@@ -329,11 +1143,51 @@ impl Cmd {
 
         let arg_str = arg.to_string_lossy().to_string();
         if arg_str != IGNORE_CMD && !self.args.iter().any(|cmd| *cmd != IGNORE_CMD) {
-            let v: Vec<&str> = arg_str.split('=').collect();
-            if v.len() == 2 && v[0].chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
-                self.vars.insert(v[0].into(), v[1].into());
+            if arg_str == RAW_CMD && !self.raw {
+                self.raw = true;
                 return self;
             }
+            if arg_str == QUIET_STDERR_CMD && !self.quiet_stderr {
+                self.quiet_stderr = true;
+                return self;
+            }
+            if arg_str == QUIET_STDOUT_CMD && !self.quiet_stdout {
+                self.quiet_stdout = true;
+                return self;
+            }
+            if arg_str == SHOW_STDOUT_CMD && !self.inherit_stdout {
+                self.inherit_stdout = true;
+                return self;
+            }
+            if arg_str == SHOW_STDERR_CMD && !self.inherit_stderr {
+                self.inherit_stderr = true;
+                return self;
+            }
+            if arg_str == SWAP_OUT_ERR_CMD && !self.swap_out_err {
+                self.swap_out_err = true;
+                return self;
+            }
+            // only the first '=' splits, the rest belongs to the value verbatim
+            if let Some((key, value)) = arg_str.split_once('=') {
+                if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.vars.insert(key.into(), value.into());
+                    return self;
+                }
+            }
+            self.in_cmd_map = CMD_MAP.lock().unwrap().contains_key(arg);
+        }
+        self.args.push(arg.to_os_string());
+        self
+    }
+
+    // adds a value substituted at runtime, which is never taken as an env assignment or a
+    // modifier, like `quiet_stderr`, whatever it contains
+    pub(crate) fn add_literal_arg<O: AsRef<OsStr>>(mut self, arg: O) -> Self {
+        let arg = arg.as_ref();
+        if arg.is_empty() {
+            return self;
+        }
+        if !self.args.iter().any(|cmd| *cmd != IGNORE_CMD) {
             self.in_cmd_map = CMD_MAP.lock().unwrap().contains_key(arg);
         }
         self.args.push(arg.to_os_string());
@@ -356,6 +1210,71 @@ impl Cmd {
         self
     }
 
+    /// Reads stdin of this command from an already open file, such as a temporary file.
+    pub fn stdin_file(self, file: File) -> Self {
+        self.add_redirect(Redirect::OpenFileToStdin(file))
+    }
+
+    /// Writes stdout of this command into an already open file.
+    pub fn stdout_file(self, file: File) -> Self {
+        self.add_redirect(Redirect::StdoutToOpenFile(file))
+    }
+
+    /// Writes stderr of this command into an already open file.
+    pub fn stderr_file(self, file: File) -> Self {
+        self.add_redirect(Redirect::StderrToOpenFile(file))
+    }
+
+    /// Runs the statement of this command only if `cond` is true.
+    pub fn when(mut self, cond: bool) -> Self {
+        self.enabled = cond;
+        self
+    }
+
+    /// Sets the file mode creation mask of this command, for the files created by the spawned
+    /// process and by its redirections. It has no effect on builtin commands other than `touch`,
+    /// or on non-unix platforms.
+    pub fn umask(mut self, mode: u32) -> Self {
+        self.umask = Some(mode);
+        self
+    }
+
+    /// Prefixes the output lines of this command with `[label]`, its stdout lines forwarded to
+    /// the parent's stdout, and its stderr lines logged as usual.
+    pub fn with_label(mut self, label: OsString) -> Self {
+        self.label = Some(label.to_string_lossy().to_string());
+        self
+    }
+
+    /// Runs this command only in `dir`, relative to the current directory of the group.
+    pub fn with_current_dir(mut self, dir: PathBuf) -> Self {
+        self.current_dir = Some(dir);
+        self
+    }
+
+    /// Appends `tail` to the command line as is, bypassing the quoting of arguments.
+    ///
+    /// This is an escape hatch for Windows programs with their own parsing rules, such as
+    /// `cmd /c`. The caller is responsible for escaping `tail` correctly.
+    #[cfg(windows)]
+    pub fn raw_command_line(mut self, tail: &str) -> Self {
+        self.raw = true;
+        self.args.push(tail.into());
+        self
+    }
+
+    /// Writes stdout of this command directly to the parent's stdout, even inside a pipe.
+    pub fn inherit_stdout(mut self) -> Self {
+        self.inherit_stdout = true;
+        self
+    }
+
+    /// Writes stderr of this command directly to the parent's stderr, without logging it.
+    pub fn inherit_stderr(mut self) -> Self {
+        self.inherit_stderr = true;
+        self
+    }
+
     fn arg0(&self) -> OsString {
         let mut args = self.args.iter().skip_while(|cmd| *cmd == IGNORE_CMD);
         if let Some(arg) = args.next() {
@@ -368,12 +1287,56 @@ impl Cmd {
         self.vars
             .iter()
             .map(|(k, v)| format!("{k}={v:?}"))
+            .chain(self.raw.then(|| RAW_CMD.to_string()))
+            .chain(self.quiet_stderr.then(|| QUIET_STDERR_CMD.to_string()))
+            .chain(self.quiet_stdout.then(|| QUIET_STDOUT_CMD.to_string()))
+            .chain(self.inherit_stdout.then(|| SHOW_STDOUT_CMD.to_string()))
+            .chain(self.inherit_stderr.then(|| SHOW_STDERR_CMD.to_string()))
+            .chain(self.swap_out_err.then(|| SWAP_OUT_ERR_CMD.to_string()))
             .chain(self.args.iter().map(|s| format!("{s:?}")))
             .chain(self.redirects.iter().map(|r| format!("{r:?}")))
             .collect::<Vec<String>>()
             .join(" ")
     }
 
+    // the command line as shown by xtrace mode, with arguments quoted only when needed
+    fn xtrace_str(&self) -> String {
+        let quote = |s: &str| {
+            if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+                format!("{s:?}")
+            } else {
+                s.to_string()
+            }
+        };
+        self.vars
+            .iter()
+            .map(|(k, v)| format!("{k}={}", quote(v)))
+            .chain(
+                self.args
+                    .iter()
+                    .skip_while(|cmd| *cmd == IGNORE_CMD)
+                    .map(|s| quote(&s.to_string_lossy())),
+            )
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn add_exports(&mut self, exports: &BTreeMap<String, String>) {
+        for (key, value) in exports {
+            if self.vars.contains_key(key) {
+                continue;
+            }
+            match self.std_cmd.as_mut() {
+                Some(cmd) => {
+                    cmd.env(key, value);
+                }
+                None => {
+                    self.vars.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
     fn gen_command(mut self) -> (bool, Self) {
         let args: Vec<OsString> = self
             .args
@@ -381,9 +1344,17 @@ impl Cmd {
             .skip_while(|cmd| *cmd == IGNORE_CMD)
             .map(|s| s.into())
             .collect();
+        if self.in_cmd_map && !builtin_handles(&args) {
+            self.in_cmd_map = false;
+        }
         if !self.in_cmd_map {
-            let mut cmd = Command::new(&args[0]);
-            cmd.args(&args[1..]);
+            let program = PROGRAM_ALIASES.lock().unwrap().get(&args[0]).cloned();
+            let mut cmd = Command::new(program.as_ref().unwrap_or(&args[0]));
+            if self.raw {
+                Self::add_raw_args(&mut cmd, &args[1..]);
+            } else {
+                cmd.args(&args[1..]);
+            }
             for (k, v) in self.vars.iter() {
                 cmd.env(k, v);
             }
@@ -392,10 +1363,65 @@ impl Cmd {
         (self.args.len() > args.len(), self)
     }
 
-    fn spawn(mut self, current_dir: &mut PathBuf, with_output: bool) -> Result<CmdChild> {
+    #[cfg(windows)]
+    fn add_raw_args(cmd: &mut Command, args: &[OsString]) {
+        use std::os::windows::process::CommandExt;
+        for arg in args {
+            cmd.raw_arg(arg);
+        }
+    }
+
+    // arguments are never re-quoted outside of Windows, so raw ones are passed as usual
+    #[cfg(not(windows))]
+    fn add_raw_args(cmd: &mut Command, args: &[OsString]) {
+        cmd.args(args);
+    }
+
+    fn spawn(self, current_dir: &mut PathBuf, with_output: bool) -> Result<CmdChild> {
+        let label = self.label.clone();
+        self.spawn_child(current_dir, with_output)
+            .map(|child| child.with_label(label, !with_output))
+    }
+
+    fn spawn_child(mut self, current_dir: &mut PathBuf, with_output: bool) -> Result<CmdChild> {
+        let mut cmd_dir;
+        let current_dir = if let Some(dir) = self.current_dir.take() {
+            // not shared with the following commands, so `cd` here has no effect on them
+            cmd_dir = current_dir.join(dir);
+            &mut cmd_dir
+        } else {
+            current_dir
+        };
+        if xtrace_enabled() {
+            info!("+ {}", self.xtrace_str());
+        }
         let arg0 = self.arg0();
-        if arg0 == CD_CMD {
-            self.run_cd_cmd(current_dir, &self.file, self.line)?;
+        if arg0.is_empty() && strict_empty_enabled() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "empty command [{}] at {}:{}",
+                    self.cmd_str(),
+                    self.file,
+                    self.line
+                ),
+            ));
+        }
+        if arg0 == EXPORT_CMD {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{EXPORT_CMD}: only supported as a whole statement at {}:{}",
+                    self.file, self.line
+                ),
+            ));
+        }
+        if arg0 == CD_CMD || arg0 == LABEL_CMD {
+            if arg0 == CD_CMD {
+                self.run_cd_cmd(current_dir, &self.file, self.line)?;
+            } else {
+                self.run_label_cmd();
+            }
             Ok(CmdChild::new(
                 CmdChildHandle::SyncFn,
                 self.cmd_str(),
@@ -420,6 +1446,9 @@ impl Cmd {
                 } else {
                     current_dir.clone()
                 },
+                umask: self.umask,
+                file: self.file.clone(),
+                line: self.line,
                 stdin: if let Some(redirect_in) = self.stdin_redirect.take() {
                     redirect_in
                 } else {
@@ -438,7 +1467,7 @@ impl Cmd {
             };
 
             let internal_cmd = CMD_MAP.lock().unwrap()[&arg0];
-            if pipe_out || with_output {
+            if pipe_out || with_output || self.label.is_some() {
                 let handle = thread::Builder::new().spawn(move || internal_cmd(&mut env))?;
                 Ok(CmdChild::new(
                     CmdChildHandle::Thread(handle),
@@ -467,6 +1496,18 @@ impl Cmd {
                 cmd.current_dir(current_dir.clone());
             }
 
+            #[cfg(unix)]
+            if let Some(mode) = self.umask {
+                use std::os::unix::process::CommandExt;
+                // SAFETY: umask() is async-signal-safe, and it can not fail
+                unsafe {
+                    cmd.pre_exec(move || {
+                        libc::umask(mode as libc::mode_t);
+                        Ok(())
+                    });
+                }
+            }
+
             // update stdin
             if let Some(redirect_in) = self.stdin_redirect.take() {
                 cmd.stdin(redirect_in);
@@ -483,7 +1524,37 @@ impl Cmd {
             }
 
             // spawning process
-            let child = cmd.spawn()?;
+            let child = cmd.spawn().map_err(|e| {
+                // log failed launches even without debug mode, unless ignored
+                if !self.ignore_error {
+                    let cmd_str = self.cmd_str();
+                    if let Some(label) = current_label() {
+                        warn!(
+                            "[{label}] Failed to spawn [{cmd_str}] at {}:{}: {e}",
+                            self.file, self.line
+                        );
+                    } else {
+                        warn!(
+                            "Failed to spawn [{cmd_str}] at {}:{}: {e}",
+                            self.file, self.line
+                        );
+                    }
+                }
+                // NotFound is also returned for a missing current directory
+                if e.kind() == ErrorKind::NotFound
+                    && (current_dir.as_os_str().is_empty() || current_dir.is_dir())
+                {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("command not found: {}", arg0.to_string_lossy()),
+                    )
+                } else {
+                    e
+                }
+            })?;
+            // close the parent's copies of the redirected handles right away, so pipe readers
+            // see EOF once the child exits and no fds are kept until the handle is waited
+            drop(cmd);
             Ok(CmdChild::new(
                 CmdChildHandle::Proc(child),
                 self.cmd_str(),
@@ -496,20 +1567,27 @@ impl Cmd {
     }
 
     fn run_cd_cmd(&self, current_dir: &mut PathBuf, file: &str, line: u32) -> CmdResult {
-        if self.args.len() == 1 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "{CD_CMD}: missing directory at {file}:{line}",
-            ));
-        } else if self.args.len() > 2 {
+        // `cd -p <dir>` or `cd --create <dir>` creates the directory at first
+        let (create, args) = match self.args.get(1) {
+            Some(flag) if flag == "-p" || flag == "--create" => (true, &self.args[2..]),
+            _ => (false, &self.args[1..]),
+        };
+        if args.is_empty() {
+            let err_msg = format!("{CD_CMD}: missing directory at {file}:{line}");
+            return Err(Error::other(err_msg));
+        } else if args.len() > 1 {
             let err_msg = format!("{CD_CMD}: too many arguments at {file}:{line}");
-            return Err(Error::new(ErrorKind::Other, err_msg));
+            return Err(Error::other(err_msg));
         }
 
-        let dir = current_dir.join(&self.args[1]);
+        let dir = current_dir.join(&args[0]);
+        if create {
+            std::fs::create_dir_all(&dir)
+                .map_err(|e| Error::new(e.kind(), format!("{CD_CMD}: {e} at {file}:{line}")))?;
+        }
         if !dir.is_dir() {
             let err_msg = format!("{CD_CMD}: No such file or directory at {file}:{line}");
-            return Err(Error::new(ErrorKind::Other, err_msg));
+            return Err(Error::other(err_msg));
         }
 
         dir.access(AccessMode::EXECUTE)?;
@@ -517,51 +1595,164 @@ impl Cmd {
         Ok(())
     }
 
-    fn open_file(path: &Path, read_only: bool, append: bool) -> Result<File> {
+    fn run_label_cmd(&self) {
+        let label = self
+            .args
+            .iter()
+            .skip_while(|cmd| *cmd == IGNORE_CMD)
+            .skip(1)
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if label.is_empty() {
+            CURRENT_LABEL.with(|current| *current.borrow_mut() = None);
+        } else {
+            info!("=== {label} ===");
+            CURRENT_LABEL.with(|current| *current.borrow_mut() = Some(label));
+        }
+    }
+
+    // refuse to truncate an existing regular file in noclobber mode, unless `>>` or `>|` is used
+    fn check_noclobber(path: &Path, allowed: bool) -> CmdResult {
+        if !allowed && noclobber_enabled() && path.is_file() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("cannot overwrite existing file {:?}", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    // fails if the program is not found, before the output files are created or truncated
+    fn check_program(&self) -> CmdResult {
+        let cmd = match self.std_cmd {
+            Some(ref cmd) => cmd,
+            None => return Ok(()),
+        };
+        let program = Path::new(cmd.get_program());
+        if program.components().count() > 1 {
+            // relative to the current directory of the group, checked when spawning
+            return Ok(());
+        }
+        let paths = match cmd.get_envs().find(|(k, _)| *k == "PATH") {
+            Some((_, paths)) => paths.map(OsString::from),
+            None => std::env::var_os("PATH"),
+        };
+        match paths {
+            Some(paths) if !find_in_paths(program, &paths) => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("command not found: {}", program.display()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn open_file(path: &Path, read_only: bool, append: bool, umask: Option<u32>) -> Result<File> {
         if read_only {
             OpenOptions::new().read(true).open(path)
         } else {
-            OpenOptions::new()
+            #[cfg_attr(not(unix), allow(unused_variables))]
+            let created = umask.is_some() && !path.exists();
+            let file = OpenOptions::new()
                 .create(true)
                 .truncate(!append)
                 .write(true)
                 .append(append)
-                .open(path)
+                .open(path)?;
+            #[cfg(unix)]
+            if let (true, Some(mode)) = (created, umask) {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(std::fs::Permissions::from_mode(0o666 & !mode))?;
+            }
+            Ok(file)
         }
     }
 
+    // attach stdin, stdout and stderr to a new pseudo-terminal, and capture all its output
+    #[cfg(feature = "pty")]
+    fn setup_pty(&mut self) -> CmdResult {
+        let (terminal, output) = crate::pty::open_pty()?;
+        self.stdin_redirect = Some(CmdIn::file(terminal.try_clone()?));
+        self.stdout_redirect = Some(CmdOut::file(terminal.try_clone()?));
+        self.stderr_redirect = Some(CmdOut::file(terminal));
+        self.stdout_logging = Some(output);
+        Ok(())
+    }
+
     fn setup_redirects(
         &mut self,
         pipe_in: &mut Option<PipeReader>,
         pipe_out: Option<PipeWriter>,
         with_output: bool,
     ) -> CmdResult {
-        // set up stdin pipe
-        if let Some(pipe) = pipe_in.take() {
-            self.stdin_redirect = Some(CmdIn::pipe(pipe));
-        }
-        // set up stdout pipe
-        if let Some(pipe) = pipe_out {
-            self.stdout_redirect = Some(CmdOut::pipe(pipe));
-        } else if with_output {
-            let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
-            self.stdout_redirect = Some(CmdOut::pipe(pipe_writer));
-            self.stdout_logging = Some(pipe_reader);
-        }
-        // set up stderr pipe
-        let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
-        self.stderr_redirect = Some(CmdOut::pipe(pipe_writer));
-        self.stderr_logging = Some(pipe_reader);
+        if self.pty {
+            #[cfg(feature = "pty")]
+            self.setup_pty()?;
+        } else {
+            // set up stdin pipe
+            if let Some(pipe) = pipe_in.take() {
+                self.stdin_redirect = Some(CmdIn::pipe(pipe));
+            }
+            // set up stdout pipe
+            if self.quiet_stdout {
+                self.stdout_redirect = Some(CmdOut::null());
+            } else if self.inherit_stdout {
+                self.stdout_redirect = Some(CmdOut::pipe(os_pipe::dup_stdout()?));
+            } else if let Some(pipe) = pipe_out {
+                self.stdout_redirect = Some(CmdOut::pipe(pipe));
+            } else if with_output || self.label.is_some() {
+                // labeled output is forwarded with a prefix, instead of being inherited
+                let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
+                self.stdout_redirect = Some(CmdOut::pipe(pipe_writer));
+                self.stdout_logging = Some(pipe_reader);
+            }
+            // set up stderr pipe
+            if self.quiet_stderr {
+                self.stderr_redirect = Some(CmdOut::null());
+            } else if self.inherit_stderr || inherit_stderr_enabled() {
+                self.stderr_redirect = Some(CmdOut::pipe(os_pipe::dup_stderr()?));
+            } else {
+                let (pipe_reader, pipe_writer) = os_pipe::pipe()?;
+                self.stderr_redirect = Some(CmdOut::pipe(pipe_writer));
+                self.stderr_logging = Some(pipe_reader);
+            }
+            // like `3>&1 1>&2 2>&3`, before the redirects of the command, which still apply to
+            // its own stdout and stderr
+            if self.swap_out_err {
+                let stdout = match self.stdout_redirect.take() {
+                    Some(redirect) => redirect,
+                    None => CmdOut::pipe(os_pipe::dup_stdout()?),
+                };
+                self.stdout_redirect = self.stderr_redirect.take();
+                self.stderr_redirect = Some(stdout);
+            }
+        }
 
+        if self.redirects.iter().any(|redirect| {
+            matches!(
+                redirect,
+                Redirect::StdoutToFile(..) | Redirect::StderrToFile(..)
+            )
+        }) {
+            self.check_program()?;
+        }
         for redirect in self.redirects.iter() {
             match redirect {
                 Redirect::FileToStdin(path) => {
                     self.stdin_redirect = Some(if path == Path::new("/dev/null") {
                         CmdIn::null()
                     } else {
-                        CmdIn::file(Self::open_file(path, true, false)?)
+                        CmdIn::file(Self::open_file(path, true, false, None)?)
                     });
                 }
+                Redirect::HereDoc(content) => {
+                    let (pipe_reader, mut pipe_writer) = os_pipe::pipe()?;
+                    let content = content.to_string_lossy().into_owned();
+                    thread::Builder::new().spawn(move || {
+                        let _ = pipe_writer.write_all(content.as_bytes());
+                    })?;
+                    self.stdin_redirect = Some(CmdIn::pipe(pipe_reader));
+                }
                 Redirect::StdoutToStderr => {
                     if let Some(ref redirect) = self.stderr_redirect {
                         self.stdout_redirect = Some(redirect.try_clone()?);
@@ -576,20 +1767,31 @@ impl Cmd {
                         self.stderr_redirect = Some(CmdOut::pipe(os_pipe::dup_stdout()?));
                     }
                 }
-                Redirect::StdoutToFile(path, append) => {
+                Redirect::StdoutToFile(path, append, force) => {
                     self.stdout_redirect = Some(if path == Path::new("/dev/null") {
                         CmdOut::null()
                     } else {
-                        CmdOut::file(Self::open_file(path, false, *append)?)
+                        Self::check_noclobber(path, *append || *force)?;
+                        CmdOut::file(Self::open_file(path, false, *append, self.umask)?)
                     });
                 }
-                Redirect::StderrToFile(path, append) => {
+                Redirect::StderrToFile(path, append, force) => {
                     self.stderr_redirect = Some(if path == Path::new("/dev/null") {
                         CmdOut::null()
                     } else {
-                        CmdOut::file(Self::open_file(path, false, *append)?)
+                        Self::check_noclobber(path, *append || *force)?;
+                        CmdOut::file(Self::open_file(path, false, *append, self.umask)?)
                     });
                 }
+                Redirect::OpenFileToStdin(file) => {
+                    self.stdin_redirect = Some(CmdIn::file(file.try_clone()?));
+                }
+                Redirect::StdoutToOpenFile(file) => {
+                    self.stdout_redirect = Some(CmdOut::file(file.try_clone()?));
+                }
+                Redirect::StderrToOpenFile(file) => {
+                    self.stderr_redirect = Some(CmdOut::file(file.try_clone()?));
+                }
             }
         }
         Ok(())
@@ -607,6 +1809,99 @@ impl<T: ToString> AsOsStr for T {
     }
 }
 
+// types which are not `ToString` get their own trait, without conflicting impls
+#[doc(hidden)]
+pub trait AsOsStrExt {
+    fn as_os_str(&self) -> OsString;
+}
+
+// in seconds, which is accepted by `sleep` and `timeout`, e.g. "1.5" for 1500ms
+impl AsOsStrExt for Duration {
+    fn as_os_str(&self) -> OsString {
+        let (secs, nanos) = (self.as_secs(), self.subsec_nanos());
+        if nanos == 0 {
+            return secs.to_string().into();
+        }
+        let fraction = format!("{nanos:09}");
+        format!("{secs}.{}", fraction.trim_end_matches('0')).into()
+    }
+}
+
+#[cfg(unix)]
+impl AsOsStrExt for [u8] {
+    fn as_os_str(&self) -> OsString {
+        use std::os::unix::ffi::OsStrExt;
+        OsStr::from_bytes(self).into()
+    }
+}
+
+#[doc(hidden)]
+pub trait IntoOsArgs {
+    fn into_os_args(self) -> Vec<OsString>;
+
+    // `$[var; "sep"]` joins the elements into one argument
+    fn join_os_args(self, sep: &str) -> OsString
+    where
+        Self: Sized,
+    {
+        let mut joined = OsString::new();
+        for (i, arg) in self.into_os_args().into_iter().enumerate() {
+            if i > 0 {
+                joined.push(sep);
+            }
+            joined.push(arg);
+        }
+        joined
+    }
+}
+
+impl<I, O> IntoOsArgs for I
+where
+    I: IntoIterator<Item = O>,
+    O: AsRef<OsStr>,
+{
+    fn into_os_args(self) -> Vec<OsString> {
+        self.into_iter().map(|arg| arg.as_ref().into()).collect()
+    }
+}
+
+// maps are not iterators of `AsRef<OsStr>`, so they get their own trait, as env assignments
+#[doc(hidden)]
+pub trait IntoOsArgsExt {
+    fn into_os_args(self) -> Vec<OsString>;
+}
+
+impl<K: AsRef<OsStr>, V: AsRef<OsStr>, S> IntoOsArgsExt for HashMap<K, V, S> {
+    fn into_os_args(self) -> Vec<OsString> {
+        self.iter().map(|(k, v)| env_assignment(k, v)).collect()
+    }
+}
+
+impl<K: AsRef<OsStr>, V: AsRef<OsStr>, S> IntoOsArgsExt for &HashMap<K, V, S> {
+    fn into_os_args(self) -> Vec<OsString> {
+        self.iter().map(|(k, v)| env_assignment(k, v)).collect()
+    }
+}
+
+impl<K: AsRef<OsStr>, V: AsRef<OsStr>> IntoOsArgsExt for BTreeMap<K, V> {
+    fn into_os_args(self) -> Vec<OsString> {
+        self.iter().map(|(k, v)| env_assignment(k, v)).collect()
+    }
+}
+
+impl<K: AsRef<OsStr>, V: AsRef<OsStr>> IntoOsArgsExt for &BTreeMap<K, V> {
+    fn into_os_args(self) -> Vec<OsString> {
+        self.iter().map(|(k, v)| env_assignment(k, v)).collect()
+    }
+}
+
+fn env_assignment<K: AsRef<OsStr>, V: AsRef<OsStr>>(key: K, value: V) -> OsString {
+    let mut assignment = key.as_ref().to_os_string();
+    assignment.push("=");
+    assignment.push(value);
+    assignment
+}
+
 #[doc(hidden)]
 #[derive(Default)]
 pub struct CmdString(OsString);
@@ -643,9 +1938,28 @@ impl fmt::Display for CmdString {
     }
 }
 
+impl fmt::Debug for CmdString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // show the exact content with escapes, the same as in the running command string
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+// copies the reader into the stdin pipe of commands, failing with the errors of the reader, but
+// not with a broken pipe, once the commands exit without reading all the input
+fn spawn_stdin_copy(
+    mut reader: Box<dyn Read + Send>,
+    mut pipe_writer: PipeWriter,
+) -> Result<JoinHandle<CmdResult>> {
+    thread::Builder::new().spawn(move || match std::io::copy(&mut reader, &mut pipe_writer) {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(()),
+    })
+}
+
 pub(crate) fn new_cmd_io_error(e: &Error, command: &str, file: &str, line: u32) -> Error {
-    Error::new(
-        e.kind(),
+    rewrap_error(
+        e,
         format!("Running [{command}] failed: {e} at {file}:{line}"),
     )
 }
@@ -670,7 +1984,7 @@ mod tests {
         assert_eq!(
             Cmds::default()
                 .pipe(Cmd::default().add_args(["echo", "rust"]))
-                .run_fun(&mut current_dir)
+                .run_fun_until(&mut current_dir, None, true)
                 .unwrap(),
             "rust"
         );
@@ -679,19 +1993,179 @@ mod tests {
             Cmds::default()
                 .pipe(Cmd::default().add_args(["echo", "rust"]))
                 .pipe(Cmd::default().add_args(["wc", "-c"]))
-                .run_fun(&mut current_dir)
+                .run_fun_until(&mut current_dir, None, true)
                 .unwrap()
                 .trim(),
             "5"
         );
     }
 
+    #[test]
+    fn test_run_cmd_with_reader() {
+        assert!(Cmds::default()
+            .pipe(Cmd::default().add_args(["grep", "rust"]))
+            .run_cmd_with_reader(Box::new("I love rust\n".as_bytes()))
+            .is_ok());
+
+        assert!(Cmds::default()
+            .pipe(Cmd::default().add_args(["cat"]))
+            .pipe(Cmd::default().add_args(["grep", "rust"]))
+            .run_cmd_with_reader(Box::new("no match\n".as_bytes()))
+            .is_err());
+
+        // the copy thread is waited, and a failed reader fails the command
+        let err = Cmds::default()
+            .pipe(Cmd::default().add_args(["cat"]))
+            .run_cmd_with_reader(Box::new(FailingReader))
+            .unwrap_err();
+        assert!(err.to_string().contains("bad reader"), "{}", err);
+        // but not the command exiting without reading all the input
+        assert!(Cmds::default()
+            .pipe(Cmd::default().add_args(["head", "-c", "1"]))
+            .run_cmd_with_reader(Box::new(std::io::repeat(b'x')))
+            .is_ok());
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+            Err(Error::other("bad reader"))
+        }
+    }
+
+    #[test]
+    fn test_share_stdin() {
+        let read_line = || Cmds::default().pipe(Cmd::default().add_args(["sh", "-c", "read a"]));
+        let mut group = GroupCmds::default()
+            .append(read_line())
+            .append(read_line())
+            .append(Cmds::default().pipe(Cmd::default().add_args(["cat"])))
+            .share_stdin(true);
+        group.set_stdin_reader(Box::new("1\n2\n3\n4\n".as_bytes()));
+        assert_eq!(group.run_fun().unwrap(), "3\n4");
+
+        let cat = || Cmds::default().pipe(Cmd::default().add_args(["cat"]));
+        let mut group = GroupCmds::default()
+            .append(cat())
+            .append(cat())
+            .share_stdin(true);
+        let err = group
+            .run_cmd_with_reader(Box::new(FailingReader))
+            .unwrap_err();
+        assert!(err.to_string().contains("bad reader"), "{}", err);
+    }
+
+    #[test]
+    fn test_inherit_stdout() {
+        let mut current_dir = PathBuf::new();
+        assert_eq!(
+            Cmds::default()
+                .pipe(Cmd::default().add_args(["echo", "rust"]))
+                .pipe(Cmd::default().add_args(["cat"]).inherit_stdout())
+                .run_fun_until(&mut current_dir, None, true)
+                .unwrap(),
+            ""
+        );
+        assert!(Cmds::default()
+            .pipe(Cmd::default().add_args(["ls", "/nofile"]).inherit_stderr())
+            .run_cmd(&mut current_dir)
+            .is_err());
+    }
+
+    #[test]
+    fn test_open_file_redirects() {
+        let path = std::env::temp_dir().join("cmd_lib_open_file_redirects");
+        let mut current_dir = PathBuf::new();
+        let out = File::create(&path).unwrap();
+        assert!(Cmds::default()
+            .pipe(
+                Cmd::default()
+                    .add_args(["sh", "-c", "echo out; echo err >&2"])
+                    .stdout_file(out.try_clone().unwrap())
+                    .stderr_file(out)
+            )
+            .run_cmd(&mut current_dir)
+            .is_ok());
+        assert_eq!(
+            Cmds::default()
+                .pipe(
+                    Cmd::default()
+                        .add_args(["cat"])
+                        .stdin_file(File::open(&path).unwrap())
+                )
+                .run_fun_until(&mut current_dir, None, true)
+                .unwrap(),
+            "out\nerr"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_to_command_string() {
+        let mut group = GroupCmds::default()
+            .append(
+                Cmds::default()
+                    .pipe(Cmd::default().add_args(["echo", "a b"]))
+                    .pipe(Cmd::default().add_args(["wc", "-c"])),
+            )
+            .append(Cmds::default().pipe(Cmd::default().add_args(["ls"]).when(false)))
+            .append(Cmds::default().pipe(Cmd::default().add_args(["pwd"])));
+        assert_eq!(
+            group.to_command_string().unwrap(),
+            r#""echo" "a b" | "wc" "-c"; "pwd""#
+        );
+    }
+
+    #[test]
+    fn test_xtrace() {
+        let cmd = Cmd::default().add_args(["ignore", "FOO=a b", "grep", "-e", "it's"]);
+        assert_eq!(cmd.xtrace_str(), r#"FOO="a b" grep -e "it's""#);
+
+        assert!(!xtrace_enabled());
+        {
+            let _xtrace = ScopedXtrace::new(true);
+            assert!(xtrace_enabled());
+            {
+                let _inner = ScopedXtrace::new(false);
+                assert!(!xtrace_enabled());
+            }
+            assert!(xtrace_enabled());
+            let mut current_dir = PathBuf::new();
+            assert!(Cmds::default()
+                .pipe(Cmd::default().add_args(["echo", "rust"]))
+                .run_cmd(&mut current_dir)
+                .is_ok());
+        }
+        assert!(!xtrace_enabled());
+    }
+
+    #[test]
+    fn test_wait_discarding_output() {
+        let mut children = GroupCmds::default()
+            .append(Cmds::default().pipe(Cmd::default().add_args(["seq", "1", "100000"])))
+            .spawn(true)
+            .unwrap();
+        assert!(children.wait_discarding_output().is_ok());
+    }
+
+    #[test]
+    fn test_cmd_string_debug() {
+        let s = CmdString::default().append("a b").append("\"c\"\t");
+        assert_eq!(format!("{s}"), "a b\"c\"\t");
+        assert_eq!(format!("{s:?}"), r#""a b\"c\"\t""#);
+    }
+
     #[test]
     fn test_stdout_redirect() {
         let mut current_dir = PathBuf::new();
         let tmp_file = "/tmp/file_echo_rust";
         let mut write_cmd = Cmd::default().add_args(["echo", "rust"]);
-        write_cmd = write_cmd.add_redirect(Redirect::StdoutToFile(PathBuf::from(tmp_file), false));
+        write_cmd = write_cmd.add_redirect(Redirect::StdoutToFile(
+            PathBuf::from(tmp_file),
+            false,
+            false,
+        ));
         assert!(Cmds::default()
             .pipe(write_cmd)
             .run_cmd(&mut current_dir)
@@ -701,7 +2175,7 @@ mod tests {
         assert_eq!(
             Cmds::default()
                 .pipe(read_cmd)
-                .run_fun(&mut current_dir)
+                .run_fun_until(&mut current_dir, None, true)
                 .unwrap(),
             "rust"
         );
@@ -0,0 +1,36 @@
+use nix::pty::openpty;
+use nix::sys::termios::{tcgetattr, tcsetattr, OutputFlags, SetArg};
+use os_pipe::PipeReader;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::thread;
+
+// Opens a pseudo-terminal, returning the terminal side for the child's stdio, and a pipe with
+// everything written to the terminal. The pipe is fed by a copy thread, since reading the pty
+// fails with EIO instead of returning EOF, once all terminal handles are closed.
+pub(crate) fn open_pty() -> Result<(File, PipeReader)> {
+    let pty = openpty(None, None)?;
+    // keep "\n" as is in the output, instead of translating it to "\r\n"
+    let mut termios = tcgetattr(&pty.slave)?;
+    termios.output_flags.remove(OutputFlags::ONLCR);
+    tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+
+    let mut master = File::from(pty.master);
+    let (pipe_reader, mut pipe_writer) = os_pipe::pipe()?;
+    thread::Builder::new().spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            match master.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if pipe_writer.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    })?;
+    Ok((File::from(pty.slave), pipe_reader))
+}
@@ -0,0 +1,74 @@
+use crate::FunResult;
+use std::io::Result;
+
+/// Extension methods to post-process the output of [`run_fun!()`](crate::run_fun), opt-in by
+/// importing this trait.
+pub trait FunResultExt {
+    /// Splits the output into lines, trimming whitespaces and dropping empty lines.
+    /// ```no_run
+    /// # use cmd_lib::*;
+    /// let dirs = run_fun!(du -h /var/log | sort -hr | head -n 10).nonempty_lines()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    fn nonempty_lines(self) -> Result<Vec<String>>;
+
+    /// Returns whether the output contains `pat`, or the error if the command failed.
+    /// ```no_run
+    /// # use cmd_lib::*;
+    /// assert!(run_fun!(rustc --version).contains("rustc")?);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    fn contains(self, pat: &str) -> Result<bool>;
+}
+
+impl FunResultExt for FunResult {
+    fn nonempty_lines(self) -> Result<Vec<String>> {
+        Ok(self?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    fn contains(self, pat: &str) -> Result<bool> {
+        Ok(self?.contains(pat))
+    }
+}
+
+// Removes ANSI escape sequences, like colors, cursor movements and hyperlinks, from `s`
+pub(crate) fn strip_ansi(s: &str) -> String {
+    enum State {
+        Text,
+        Esc,    // after ESC, with optional intermediate bytes
+        Csi,    // ESC [ params final
+        Osc,    // ESC ] text BEL, or ESC ] text ESC \
+        OscEsc, // ESC inside OSC, starting the terminator
+    }
+    let mut output = String::with_capacity(s.len());
+    let mut state = State::Text;
+    for c in s.chars() {
+        state = match state {
+            State::Text if c == '\x1b' => State::Esc,
+            State::Text => {
+                output.push(c);
+                State::Text
+            }
+            State::Esc => match c {
+                '[' => State::Csi,
+                ']' => State::Osc,
+                ' '..='/' => State::Esc,
+                _ => State::Text,
+            },
+            State::Csi if ('@'..='~').contains(&c) => State::Text,
+            State::Csi => State::Csi,
+            State::Osc => match c {
+                '\x07' => State::Text,
+                '\x1b' => State::OscEsc,
+                _ => State::Osc,
+            },
+            State::OscEsc => State::Text,
+        };
+    }
+    output
+}
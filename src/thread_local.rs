@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 /// Declare a new thread local storage variable.
 /// ```
 /// # use cmd_lib::*;
@@ -58,6 +60,53 @@ macro_rules! tls_set {
     };
 }
 
+/// Declare a new global variable shared by all threads, which is initialized once on first use
+/// and can not be changed afterwards.
+///
+/// Unlike [`tls_init!`], the value is not duplicated per thread, which suits read-mostly config.
+/// ```
+/// # use cmd_lib::*;
+/// tls_init_shared!(MAX_JOBS, usize, std::thread::available_parallelism().map_or(1, |n| n.get()));
+/// ```
+#[macro_export]
+macro_rules! tls_init_shared {
+    ($vis:vis $var:ident, $t:ty, $($var_init:tt)*) => {
+        $vis static $var: $crate::SharedVar<$t> = $crate::SharedVar::new(|| $($var_init)*);
+    };
+}
+
+/// Get a reference to the value of a shared global variable, declared by [`tls_init_shared!`].
+/// ```
+/// # use cmd_lib::*;
+/// tls_init_shared!(CONFIG_DIR, String, format!("{}/.config", env!("CARGO_MANIFEST_DIR")));
+/// let dir: &String = tls_get_shared!(CONFIG_DIR);
+/// ```
+#[macro_export]
+macro_rules! tls_get_shared {
+    ($var:ident) => {
+        $var.get()
+    };
+}
+
+#[doc(hidden)]
+pub struct SharedVar<T> {
+    cell: OnceLock<T>,
+    init: fn() -> T,
+}
+
+impl<T> SharedVar<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            cell: OnceLock::new(),
+            init,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        self.cell.get_or_init(self.init)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -82,4 +131,11 @@ mod tests {
         tls_set!(V, |v| v.push(200));
         assert_eq!(tls_get!(V)[0], 100);
     }
+    #[test]
+    fn test_shared_var() {
+        tls_init_shared!(NAMES, Vec<String>, vec!["a".to_string()]);
+        let handle = std::thread::spawn(|| tls_get_shared!(NAMES).len());
+        assert_eq!(handle.join().unwrap(), 1);
+        assert!(std::ptr::eq(tls_get_shared!(NAMES), tls_get_shared!(NAMES)));
+    }
 }
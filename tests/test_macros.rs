@@ -24,6 +24,39 @@ fn test_cd_fails() {
     .is_err());
 }
 
+#[test]
+#[rustfmt::skip]
+fn test_cd_create() {
+    let dir = "/tmp/cmd_lib_cd_create";
+    run_cmd!(rm -rf $dir).unwrap();
+    assert!(run_cmd!(cd $dir/a).is_err());
+    assert_eq!(run_fun!(cd -p $dir/a; pwd).unwrap(), "/tmp/cmd_lib_cd_create/a");
+    assert_eq!(run_fun!(cd --create $dir/a; pwd).unwrap(), "/tmp/cmd_lib_cd_create/a");
+    assert!(run_cmd!(cd -p).is_err());
+    assert!(run_cmd!(cd -p $dir a).is_err());
+    run_cmd!(rm -rf $dir).unwrap();
+}
+
+#[test]
+fn test_export() {
+    let output = run_fun! {
+        export CMD_LIB_A=1 CMD_LIB_B="x y";
+        CMD_LIB_B=2 printenv CMD_LIB_B;
+        printenv CMD_LIB_A CMD_LIB_B;
+    }
+    .unwrap();
+    assert_eq!(output, "1\nx y");
+    assert_eq!(
+        run_fun!(export CMD_LIB_A=1; CMD_LIB_A=2 printenv CMD_LIB_A).unwrap(),
+        "2"
+    );
+    assert!(run_cmd!(printenv CMD_LIB_B).is_err());
+    assert_eq!(std::env::var("CMD_LIB_A").ok(), None);
+
+    assert!(run_cmd!(export CMD_LIB_A).is_err());
+    assert!(run_cmd!(export CMD_LIB_A=1 | cat).is_err());
+}
+
 #[test]
 fn test_run_cmds() {
     assert!(run_cmd! {
@@ -35,11 +68,79 @@ fn test_run_cmds() {
     .is_ok());
 }
 
+#[test]
+fn test_cmd_not_found() {
+    let err = run_cmd!(bad_cmd_not_exist arg1).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(err
+        .to_string()
+        .contains("command not found: bad_cmd_not_exist"));
+}
+
+#[test]
+fn test_error_line_number() {
+    let line = line!();
+    let err = run_cmd! {
+        echo "line 1";
+        echo "line 2" | cat;
+        ls /bad_dir_for_line_number;
+    }
+    .unwrap_err();
+    assert!(err.to_string().ends_with(&format!(":{}", line + 4)));
+}
+
+#[test]
+fn test_error_statement_index() {
+    let err = run_cmd! {
+        echo "line 1";
+        ls /bad_dir_for_statement;
+        echo "line 3";
+    }
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("statement 2/3: Running [\"ls\""));
+
+    let err = run_fun!(echo xx; cat /bad_file_for_statement).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("statement 2/2: Running [\"cat\""));
+
+    let err = run_cmd!(ls / bad_dir_for_statement).unwrap_err();
+    assert!(err.to_string().starts_with("Running [\"ls\""));
+}
+
 #[test]
 fn test_run_fun() {
     assert!(run_fun!(uptime).is_ok());
 }
 
+#[test]
+fn test_run_fun_plain() {
+    let colored =
+        "\x1b[1;31mred\x1b[0m \x1b(Bplain \x1b]8;;https://x.y\x07link\x1b]8;;\x1b\\\n\x1b[0m";
+    assert_eq!(
+        run_fun_plain!(printf "%s" $colored).unwrap(),
+        "red plain link"
+    );
+    assert_eq!(run_fun_plain!(echo "a\x1b[2Kb").unwrap(), "ab");
+    assert!(run_fun!(printf "%s" $colored).unwrap().contains('\x1b'));
+    assert!(run_fun_plain!(false).is_err());
+}
+
+#[test]
+fn test_run_fun_untrimmed() {
+    assert_eq!(run_fun_untrimmed!(echo rust).unwrap(), "rust\n");
+    assert_eq!(run_fun_untrimmed!(printf "a\n\n").unwrap(), "a\n\n");
+    assert_eq!(run_fun_untrimmed!(echo x; echo -n y).unwrap(), "y");
+    assert!(run_fun_untrimmed!(false).is_err());
+    let output = spawn_with_output!(echo rust)
+        .unwrap()
+        .wait_with_untrimmed_output()
+        .unwrap();
+    assert_eq!(output, "rust\n");
+}
+
 #[test]
 fn test_args_passing() {
     let dir: &str = "folder";
@@ -70,12 +171,81 @@ fn test_args_with_spaces_check_result() {
     assert!(run_cmd!(rmdir /tmp/$dir).is_ok());
 }
 
+#[test]
+fn test_quoted_vars_as_single_arg() {
+    let a = "hello world";
+    let b = "with  spaces; and | separators";
+    assert_eq!(run_fun!(bash -c r"echo $#" bash "$a $b").unwrap(), "1");
+    assert_eq!(run_fun!(bash -c r"echo $#" bash "${a}${b}").unwrap(), "1");
+    assert_eq!(run_fun!(bash -c r"echo $#" bash x"$a"-$b).unwrap(), "1");
+    assert_eq!(run_fun!(bash -c r"echo $#" bash $a $b).unwrap(), "2");
+    assert_eq!(
+        run_fun!(bash -c r#"echo "$1""# bash "$a $b").unwrap(),
+        "hello world with  spaces; and | separators"
+    );
+}
+
+#[test]
+fn test_quoted_separators() {
+    assert_eq!(run_fun!(echo "a|b").unwrap(), "a|b");
+    assert_eq!(
+        run_fun!(printf "%s|" "a;b" "x>y" "<in" "&&" "2>&1").unwrap(),
+        "a;b|x>y|<in|&&|2>&1|"
+    );
+    assert_eq!(
+        run_fun!(printf "%s|" r"|" r";" r">>" r"<<").unwrap(),
+        "||;|>>|<<|"
+    );
+    assert_eq!(
+        run_fun!(echo "a b" | awk "-F|" r"{print $1}").unwrap(),
+        "a b"
+    );
+}
+
+#[test]
+fn test_adjacent_literals_concat() {
+    let c = "c";
+    assert_eq!(run_fun!(echo "a""b").unwrap(), "ab");
+    assert_eq!(run_fun!(echo "a""b" "c").unwrap(), "ab c");
+    assert_eq!(run_fun!(echo "a""b""$c").unwrap(), "abc");
+    assert_eq!(run_fun!(bash -c r"echo $#" bash "a""b"-x).unwrap(), "1");
+}
+
+#[test]
+fn test_quoted_vars_glued_to_literals() {
+    let v = "a b";
+    assert_eq!(run_fun!(echo pre"$v"post).unwrap(), "prea bpost");
+    assert_eq!(run_fun!(printf "%s|" pre"$v"post).unwrap(), "prea bpost|");
+    assert_eq!(run_fun!(printf "%s|" pre"$v" post).unwrap(), "prea b|post|");
+    assert_eq!(run_fun!(printf "%s|" "$v"$v"${v}").unwrap(), "a ba ba b|");
+    assert_eq!(run_fun!(printf "%s|" 1"$v"2).unwrap(), "1a b2|");
+    assert_eq!(run_fun!(printf "%s|" --opt="$v").unwrap(), "--opt=a b|");
+    assert_eq!(run_fun!(printf "%s|" r"$v"post).unwrap(), "$vpost|");
+    assert_eq!(run_fun!(printf "%s|" pre$r"$v"post).unwrap(), "prea bpost|");
+}
+
 #[test]
 fn test_non_string_args() {
     let a = 1;
     assert!(run_cmd!(sleep $a).is_ok());
 }
 
+#[test]
+fn test_numeric_and_bool_args() {
+    let i = -42;
+    let u = 18446744073709551615u64;
+    let f = 0.5;
+    let small = 1e-7;
+    let big = 1e20;
+    let b = true;
+    assert_eq!(run_fun!(echo $i $u).unwrap(), "-42 18446744073709551615");
+    assert_eq!(
+        run_fun!(echo $f "${small}" $big).unwrap(),
+        "0.5 0.0000001 100000000000000000000"
+    );
+    assert_eq!(run_fun!(echo "b=$b").unwrap(), "b=true");
+}
+
 #[test]
 fn test_non_eng_args() {
     let msg = "你好！";
@@ -125,6 +295,184 @@ fn test_vars_in_str3() {
 /// ```
 fn test_vars_in_str4() {}
 
+#[test]
+#[cfg(target_os = "linux")]
+fn test_spawn_with_output_no_fd_leak() {
+    let fd_count = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+    let before = fd_count();
+    for _ in 0..2000 {
+        let mut proc = spawn_with_output!(echo xx | cat | wc -c).unwrap();
+        assert_eq!(proc.wait_with_output().unwrap(), "3");
+    }
+    for _ in 0..5 {
+        assert!(spawn_with_output!(echo xx | bad_cmd_not_exist).is_err());
+    }
+    // other tests may be running in parallel, so allow some slack
+    assert!(fd_count() < before + 100);
+}
+
+#[test]
+fn test_wait_with_pipes() {
+    use std::io::{BufRead, BufReader};
+    let mut out = vec![];
+    let mut err = vec![];
+    let res = spawn_with_output!(bash -c "echo 1; echo 10% >&2; echo 2; echo 100% >&2")
+        .unwrap()
+        .wait_with_pipes(
+            &mut |pipe| out = BufReader::new(pipe).lines().map_while(Result::ok).collect(),
+            &mut |line| err.push(line),
+        );
+    assert!(res.is_ok());
+    assert_eq!(out, ["1", "2"]);
+    assert_eq!(err, ["10%", "100%"]);
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_spawn_with_stdin() {
+    use std::io::{BufRead, BufReader, Write};
+    let mut proc = spawn_with_stdin!(cat | cat).unwrap();
+    let mut stdin = proc.take_stdin();
+    assert!(stdin.is_some());
+    assert!(proc.take_stdin().is_none());
+    let mut replies = vec![];
+    let res = proc.wait_with_pipe(&mut |stdout| {
+        // dropped at the end, to close stdin
+        let mut stdin = stdin.take().unwrap();
+        let mut lines = BufReader::new(stdout).lines();
+        for req in ["ping", "pong"] {
+            writeln!(stdin, "{req}").unwrap();
+            stdin.flush().unwrap();
+            replies.push(lines.next().unwrap().unwrap());
+        }
+    });
+    assert!(res.is_ok());
+    assert_eq!(replies, ["ping", "pong"]);
+
+    // closed when waiting, if not taken
+    let mut proc = spawn_with_stdin!(wc -c).unwrap();
+    assert_eq!(proc.wait_with_output().unwrap(), "0");
+    assert!(spawn_with_output!(echo).unwrap().take_stdin().is_none());
+}
+
+#[test]
+fn test_output_chunks() {
+    let chunks: Vec<Vec<u8>> = spawn_with_output!(printf "abcdefg")
+        .unwrap()
+        .chunks(3)
+        .collect::<std::io::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        chunks,
+        vec![b"abc".to_vec(), b"def".to_vec(), b"g".to_vec()]
+    );
+
+    let mut chunks = spawn_with_output!(sh -c "printf abc; exit 1")
+        .unwrap()
+        .chunks(2);
+    assert_eq!(chunks.next().unwrap().unwrap(), b"ab");
+    assert_eq!(chunks.next().unwrap().unwrap(), b"c");
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+
+    // dropping early does not block on the remaining output
+    let mut chunks = spawn_with_output!(yes).unwrap().chunks(4);
+    assert_eq!(chunks.next().unwrap().unwrap(), b"y\ny\n");
+    drop(chunks);
+}
+
+#[test]
+fn test_nonempty_lines() {
+    assert_eq!(
+        run_fun!(printf "  a \n\n b\n   \n")
+            .nonempty_lines()
+            .unwrap(),
+        vec!["a", "b"]
+    );
+    assert!(run_fun!(true).nonempty_lines().unwrap().is_empty());
+    assert!(run_fun!(false).nonempty_lines().is_err());
+}
+
+#[test]
+fn test_output_contains() {
+    assert!(run_fun!(echo hello world).contains("o w").unwrap());
+    assert!(!run_fun!(echo hello).contains("world").unwrap());
+    assert!(run_fun!(false).contains("").is_err());
+}
+
+#[test]
+fn test_ignore_last_fun() {
+    assert_eq!(run_fun!(ignore sh -c "echo out; exit 1").unwrap(), "out");
+    assert_eq!(
+        run_fun!(echo first; ignore sh -c "echo out; exit 1").unwrap(),
+        "out"
+    );
+    assert_eq!(
+        run_fun!(ignore sh -c "echo out; exit 1" | cat).unwrap(),
+        "out"
+    );
+    assert_eq!(run_fun!(ignore bad_cmd_for_ignore).unwrap(), "");
+}
+
+#[test]
+fn test_wait_with_tee() {
+    let output = spawn_with_output!(echo hello | tr a-z A-Z)
+        .unwrap()
+        .wait_with_tee()
+        .unwrap();
+    assert_eq!(output, "HELLO");
+    assert!(spawn_with_output!(sh -c "echo out; exit 1")
+        .unwrap()
+        .wait_with_tee()
+        .is_err());
+    assert_eq!(
+        spawn_with_output!(ignore sh -c "echo out; exit 1")
+            .unwrap()
+            .wait_with_tee()
+            .unwrap(),
+        "out"
+    );
+}
+
+#[test]
+fn test_wait_with_all_to() {
+    let mut stdout = Vec::new();
+    let (res, stderr) = spawn_with_output!(bash -c "seq 1 3; echo oops >&2")
+        .unwrap()
+        .wait_with_all_to(&mut stdout, true);
+    assert!(res.is_ok());
+    assert_eq!(stdout, b"1\n2\n3\n");
+    assert_eq!(stderr, "oops");
+
+    let mut stdout = Vec::new();
+    let (res, stderr) = spawn_with_output!(bash -c "echo partial; echo oops >&2; false")
+        .unwrap()
+        .wait_with_all_to(&mut stdout, false);
+    assert!(res.is_err());
+    assert_eq!(stdout, b"partial\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn test_fun_children_kill() {
+    let now = std::time::Instant::now();
+    let mut proc = spawn_with_output!(sleep 10 | cat).unwrap();
+    assert!(proc.kill().is_ok());
+    assert!(now.elapsed() < std::time::Duration::from_secs(5));
+    assert!(proc.pids().is_empty());
+}
+
+#[test]
+fn test_wait_with_raw_output_and_stderr() {
+    let (mut stdout, mut stderr) = (vec![], String::new());
+    let res = spawn_with_output!(bash -c r"printf '\x00\xff'; echo bad input >&2; exit 1")
+        .unwrap()
+        .wait_with_raw_output_and_stderr(&mut stdout, &mut stderr);
+    assert!(res.is_err());
+    assert_eq!(stdout, [0, 0xff]);
+    assert_eq!(stderr, "bad input");
+}
+
 #[test]
 fn test_tls_set() {
     tls_init!(V, Vec<String>, vec![]);
@@ -145,8 +493,10 @@ fn test_pipe() {
     assert!(run_cmd!(echo xx | false | wc | wc | wc).is_err());
 
     set_pipefail(false);
+    assert!(!pipefail_enabled());
     assert!(run_cmd!(du -ah . | sort -hr | head -n 10).is_ok());
     set_pipefail(true);
+    assert!(pipefail_enabled());
 
     let wc_cmd = "wc";
     assert!(run_cmd!(ls | $wc_cmd).is_ok());
@@ -157,6 +507,7 @@ fn test_pipe() {
 /// run_cmd!(ls > >&1).unwrap();
 /// run_cmd!(ls >>&1).unwrap();
 /// run_cmd!(ls >>&2).unwrap();
+/// run_cmd!(cat << "a" < /tmp/f).unwrap();
 /// ```
 fn test_redirect() {
     let tmp_file = "/tmp/f";
@@ -179,10 +530,499 @@ fn test_redirect() {
     assert!(run_cmd!(rm -f $tmp_file $tmp_log).is_ok());
 }
 
+#[test]
+fn test_force_redirect() {
+    let f = "/tmp/cmd_lib_force_redirect";
+    run_cmd!(echo a >| $f; echo b 2>| $f >&2).unwrap();
+    assert_eq!(run_fun!(cat $f).unwrap(), "b");
+    run_cmd!(echo c &>| $f).unwrap();
+    assert_eq!(run_fun!(cat $f).unwrap(), "c");
+    run_cmd!(rm -f $f).unwrap();
+}
+
+#[test]
+fn test_builtin_touch() {
+    let dir = "/tmp/cmd_lib_touch_test";
+    run_cmd!(rm -rf $dir; mkdir -p $dir).unwrap();
+    run_cmd!(cd $dir; touch a b; touch -c c).unwrap();
+    assert_eq!(run_fun!(ls $dir).unwrap(), "a\nb");
+
+    let a = std::path::Path::new(dir).join("a");
+    run_cmd!(echo hi > $a).unwrap();
+    let f = std::fs::File::options().append(true).open(&a).unwrap();
+    f.set_modified(std::time::UNIX_EPOCH).unwrap();
+    run_cmd!(touch $a $dir).unwrap();
+    let modified = std::fs::metadata(&a).unwrap().modified().unwrap();
+    assert!(modified > std::time::UNIX_EPOCH);
+    assert_eq!(run_fun!(cat $a).unwrap(), "hi");
+
+    // other options run the touch program
+    run_cmd!(touch -d "@0" $a; touch -r $a $dir/r).unwrap();
+    let modified = std::fs::metadata(&a).unwrap().modified().unwrap();
+    assert_eq!(modified, std::time::UNIX_EPOCH);
+    let modified = std::fs::metadata(format!("{dir}/r"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(modified, std::time::UNIX_EPOCH);
+
+    assert!(run_cmd!(touch).is_err());
+    assert!(run_cmd!(touch $dir/no_such_dir/e).is_err());
+    run_cmd!(rm -rf $dir).unwrap();
+}
+
+#[test]
+fn test_redirect_missing_program() {
+    let f = "/tmp/cmd_lib_redirect_missing_program";
+    run_cmd!(echo important > $f).unwrap();
+    let err = run_cmd!(cmd_lib_no_such_program > $f).unwrap_err();
+    assert!(err.to_string().contains("command not found"));
+    assert!(run_cmd!(cmd_lib_no_such_program 2>> $f).is_err());
+    assert!(run_cmd!(PATH=/cmd_lib_no_such_dir ls &> $f).is_err());
+    assert_eq!(run_fun!(cat $f).unwrap(), "important");
+    run_cmd!(rm -f $f).unwrap();
+}
+
+#[test]
+fn test_redirect_to_path_vars() {
+    let dir = std::env::temp_dir();
+    let pathbuf_var = dir.join("cmd_lib_redirect_pathbuf");
+    run_cmd!(echo hi > $pathbuf_var).unwrap();
+    run_cmd!(echo there >> $pathbuf_var).unwrap();
+    assert_eq!(run_fun!(cat < $pathbuf_var).unwrap(), "hi\nthere");
+
+    let path_var = pathbuf_var.as_path();
+    run_cmd!(echo hello &> $path_var).unwrap();
+    assert_eq!(run_fun!(cat $path_var).unwrap(), "hello");
+
+    run_cmd!(echo joined > $dir/cmd_lib_redirect_joined).unwrap();
+    assert_eq!(
+        run_fun!(cat $dir/cmd_lib_redirect_joined).unwrap(),
+        "joined"
+    );
+
+    run_cmd!(echo substituted > $(echo $pathbuf_var)).unwrap();
+    assert_eq!(run_fun!(cat $pathbuf_var).unwrap(), "substituted");
+    run_cmd!(rm -f $pathbuf_var $dir/cmd_lib_redirect_joined).unwrap();
+}
+
+#[test]
+fn test_stdout_stderr_order_with_merge() {
+    let output = spawn_with_output!(bash -c r"echo a; echo b >&2; echo c; echo d >&2" 2>&1)
+        .unwrap()
+        .wait_with_output()
+        .unwrap();
+    assert_eq!(output, "a\nb\nc\nd");
+}
+
+#[test]
+fn test_pipe_stderr() {
+    assert_eq!(
+        run_fun!(bash -c r"echo out; echo err >&2" |& sort).unwrap(),
+        "err\nout"
+    );
+    assert_eq!(
+        run_fun!(bash -c r"echo out; echo err >&2" 2>&1 | sort).unwrap(),
+        "err\nout"
+    );
+    assert_eq!(
+        run_fun!(echo xx | bash -c r"cat; echo err >&2" |& wc -l).unwrap(),
+        "2"
+    );
+    assert_eq!(
+        run_fun!(bash -c r"echo out; echo err >&2" | sort).unwrap(),
+        "out"
+    );
+}
+
+#[test]
+fn test_program_alias() {
+    assert!(run_cmd!(cmd_lib_alias_test hi).is_err());
+    set_program_alias("cmd_lib_alias_test", "echo");
+    assert_eq!(run_fun!(cmd_lib_alias_test hi).unwrap(), "hi");
+    assert_eq!(
+        run_fun!(echo cmd_lib_alias_test).unwrap(),
+        "cmd_lib_alias_test"
+    );
+}
+
+#[test]
+fn test_command_exists() {
+    assert!(command_exists("echo"));
+    assert!(command_exists("ls"));
+    assert!(command_exists("/bin/sh"));
+    assert!(!command_exists("cmd_lib_no_such_command"));
+    assert!(!command_exists("/tmp/cmd_lib_no_such_command"));
+    set_program_alias("cmd_lib_exists_alias", "ls");
+    assert!(command_exists("cmd_lib_exists_alias"));
+}
+
+#[test]
+fn test_run_cmds_from_vec() {
+    let dir = "/tmp/cmd_lib_run_cmds";
+    let cmds: Vec<Vec<String>> = vec![
+        vec!["mkdir".into(), "-p".into(), dir.into()],
+        vec!["touch".into(), format!("{dir}/a b")],
+    ];
+    assert!(run_cmds(&cmds).is_ok());
+    assert_eq!(run_fun!(ls $dir).unwrap(), "a b");
+
+    let err = run_cmds(&[
+        vec!["true"],
+        vec!["false"],
+        vec!["touch", "/tmp/cmd_lib_run_cmds/c"],
+    ])
+    .unwrap_err();
+    assert!(err.to_string().starts_with("statement 2/3: "));
+    assert_eq!(run_fun!(ls $dir).unwrap(), "a b");
+
+    let empty: Vec<&str> = vec![];
+    assert!(run_cmds(&[empty]).is_err());
+    run_cmd!(rm -rf $dir).unwrap();
+}
+
+#[test]
+fn test_run_cmd_str() {
+    let mut syms = SymTable::new();
+    syms.set("name", "a b; rm -rf /")
+        .set_vec("words", ["x", "y z"]);
+    assert_eq!(
+        run_fun_str("echo $name | tr a-z A-Z", &syms).unwrap(),
+        "A B; RM -RF /"
+    );
+    assert_eq!(
+        run_fun_str(r#"printf '[%s]' $[words] "${name}!" \$x"#, &syms).unwrap(),
+        "[x][y z][a b; rm -rf /!][$x]"
+    );
+
+    let file = "/tmp/cmd_lib_run_cmd_str.txt";
+    syms.set("file", file);
+    assert!(run_cmd_str(
+        "echo first > $file; echo second >> $file 2>&1 # done",
+        &syms
+    )
+    .is_ok());
+    assert_eq!(run_fun_str("cat < $file", &syms).unwrap(), "first\nsecond");
+    run_cmd!(rm -f $file).unwrap();
+
+    // values are never taken as env assignments or modifiers
+    syms.set("assign", "CMD_LIB_INJECTED=1")
+        .set("modifier", "quiet_stderr");
+    assert!(run_cmd_str("$assign printenv CMD_LIB_INJECTED", &syms).is_err());
+    assert!(run_cmd_str("$modifier true", &syms).is_err());
+    assert_eq!(
+        run_fun_str("printf '[%s]' $modifier $[words]", &syms).unwrap(),
+        "[quiet_stderr][x][y z]"
+    );
+    assert_eq!(
+        run_fun_str("CMD_LIB_A=$name printenv CMD_LIB_A", &syms).unwrap(),
+        "a b; rm -rf /"
+    );
+
+    assert!(run_cmd_str("echo $undefined", &syms).is_err());
+    assert!(run_cmd_str("echo $words", &syms).is_err());
+    assert!(run_cmd_str("echo 'unterminated", &syms).is_err());
+    assert!(run_cmd_str("echo x |", &syms).is_err());
+    assert!(run_cmd_str("echo x >", &syms).is_err());
+}
+
+#[test]
+fn test_dynamic_program_name() {
+    let builtin = "echo";
+    let prog = "printf";
+    let wc = "wc";
+    let tmp_file = "/tmp/cmd_lib_dynamic_prog";
+    assert_eq!(run_fun!($builtin -n xx yy).unwrap(), "xx yy");
+    assert_eq!(run_fun!($prog "%s-%s" a b | $wc -c).unwrap(), "3");
+    assert_eq!(run_fun!(${prog} "%s" a; $builtin b).unwrap(), "b");
+    assert!(run_cmd!($prog "%s" xx > $tmp_file).is_ok());
+    assert_eq!(run_fun!($wc -c < $tmp_file).unwrap(), "2");
+    assert!(run_cmd!(ignore $prog "%d" xx 2>/dev/null).is_ok());
+    assert_eq!(run_fun!(FOO=100 $prog "%s" "$$FOO").unwrap(), "$FOO");
+    let argv = vec!["printf", "%s", "yy"];
+    assert_eq!(run_fun!($[argv]).unwrap(), "yy");
+    run_cmd!(rm -f $tmp_file).unwrap();
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_here_doc() {
+    let name = "rust";
+    assert_eq!(
+        run_fun!(cat << r#"line 1
+line $2"#).unwrap(),
+        "line 1\nline $2"
+    );
+    assert_eq!(run_fun!(wc -l <<"a\nb\n").unwrap(), "2");
+    assert_eq!(run_fun!(cat << "hello $name" | wc -w).unwrap(), "2");
+    let content = "from var";
+    assert_eq!(run_fun!(cat << $content).unwrap(), "from var");
+}
+
+#[test]
+fn test_wait_with_output_and_status() {
+    let (res, stdout) = spawn_with_output!(bash -c r"echo partial; exit 3")
+        .unwrap()
+        .wait_with_output_and_status();
+    assert!(res.unwrap_err().to_string().contains("status code: 3"));
+    assert_eq!(stdout, "partial");
+
+    let (res, stdout) = spawn_with_output!(ignore bash -c r"echo partial; exit 3")
+        .unwrap()
+        .wait_with_output_and_status();
+    assert!(res.is_ok());
+    assert_eq!(stdout, "partial");
+}
+
+#[test]
+fn test_wait_with_output_timeout() {
+    use std::time::{Duration, Instant};
+    let output = spawn_with_output!(echo xx | cat)
+        .unwrap()
+        .wait_with_output_timeout(Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(output, "xx");
+
+    let now = Instant::now();
+    let err = spawn_with_output!(sleep 10 | cat)
+        .unwrap()
+        .wait_with_output_timeout(Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(now.elapsed() < Duration::from_secs(5));
+
+    // the `sleep` grandchild keeps stdout and stderr open after `sh` is killed
+    let now = Instant::now();
+    let err = spawn_with_output!(sh -c "sleep 5; echo x")
+        .unwrap()
+        .wait_with_output_timeout(Duration::from_millis(300))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(now.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn test_wait_with_output_trimmed() {
+    let output = spawn_with_output!(printf "a\r\n")
+        .unwrap()
+        .wait_with_output_trimmed(TrimMode::Crlf)
+        .unwrap();
+    assert_eq!(output, "a");
+    let output = spawn_with_output!(printf "a \n\n")
+        .unwrap()
+        .wait_with_output_trimmed(TrimMode::None)
+        .unwrap();
+    assert_eq!(output, "a \n\n");
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_pipe_into() {
+    let first = spawn_with_output!(printf "b\na\nb\n").unwrap();
+    let next = spawn_with_stdin!(sort | uniq).unwrap();
+    let output = first.pipe_into(next).unwrap().wait_with_output().unwrap();
+    assert_eq!(output, "a\nb");
+
+    let first = spawn_with_output!(echo rust).unwrap();
+    let next = spawn_with_output!(echo next).unwrap();
+    assert!(first.pipe_into(next).is_err());
+
+    // the copy fails once `true` exits without reading its stdin
+    let first = spawn_with_output!(seq 1 1000000).unwrap();
+    let next = spawn_with_stdin!(true).unwrap();
+    let mut buf = vec![];
+    let mut proc = first.pipe_into(next).unwrap();
+    assert!(proc.wait_with_raw_output(&mut buf).is_err());
+
+    let first = spawn_with_output!(ignore seq 1 1000000).unwrap();
+    let next = spawn_with_stdin!(head -1).unwrap();
+    let mut proc = first.pipe_into(next).unwrap();
+    assert_eq!(proc.wait_with_output().unwrap(), "1");
+}
+
+#[test]
+fn test_wait_with_output_limited() {
+    use std::time::{Duration, Instant};
+    let output = spawn_with_output!(ignore seq 1 10000000 | head -1)
+        .unwrap()
+        .wait_with_output_limited(10)
+        .unwrap();
+    assert_eq!(output, "1");
+    let output = spawn_with_output!(echo xx)
+        .unwrap()
+        .wait_with_output_limited(usize::MAX)
+        .unwrap();
+    assert_eq!(output, "xx");
+
+    let now = Instant::now();
+    let err = spawn_with_output!(seq 1 10000000)
+        .unwrap()
+        .wait_with_output_limited(100)
+        .unwrap_err();
+    assert!(err.to_string().contains("output truncated after 100 bytes"));
+    assert!(now.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_wait_until_stderr_matches() {
+    use std::time::{Duration, Instant};
+    let output = spawn_with_output!(sh -c "echo warning: x >&2; echo out")
+        .unwrap()
+        .wait_until_stderr_matches(|line| line.starts_with("error:"))
+        .unwrap();
+    assert_eq!(output, "out");
+
+    let now = Instant::now();
+    let err = spawn_with_output!(sh -c "echo error: bad >&2; sleep 10" | cat)
+        .unwrap()
+        .wait_until_stderr_matches(|line| line.starts_with("error:"))
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("stopped on stderr line \"error: bad\""));
+    assert!(now.elapsed() < Duration::from_secs(5));
+
+    assert!(spawn_with_output!(false)
+        .unwrap()
+        .wait_until_stderr_matches(|_| false)
+        .is_err());
+
+    #[cfg(feature = "regex")]
+    assert!(spawn_with_output!(sh -c "echo error[E0308]: x >&2")
+        .unwrap()
+        .wait_until_stderr_matches_regex(regex::Regex::new(r"^error\[E\d+\]").unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_timeout_modifier() {
+    use std::time::{Duration, Instant};
+    assert!(run_cmd!(#[timeout(10s)] echo xx).is_ok());
+    assert_eq!(run_fun!(#[timeout(1.5m)] echo xx | cat).unwrap(), "xx");
+    assert_eq!(
+        run_fun_untrimmed!(#[timeout(500ms)] echo xx).unwrap(),
+        "xx\n"
+    );
+
+    let now = Instant::now();
+    let err = run_cmd! {
+        #[timeout(200ms)]
+        echo first;
+        sleep 10;
+    }
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(err.to_string().contains("timed out after 200ms"));
+    assert!(now.elapsed() < Duration::from_secs(5));
+
+    let limit = Duration::from_millis(100);
+    let err = run_fun!(#[timeout(limit)] sleep 10 | cat).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    // the `sleep` grandchild keeps the pipes open after `sh` is killed
+    let now = Instant::now();
+    let err = run_cmd!(#[timeout(300ms)] sh -c "sleep 5; true").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(run_cmd!(#[timeout(300ms)] sh -c "sleep 5").is_err());
+    assert!(run_fun!(#[timeout(300ms)] sh -c "sleep 5; echo xx").is_err());
+    assert!(now.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_poll_children() {
+    use std::time::{Duration, Instant};
+    let mut proc = spawn!(sleep 0.2 | sleep 0.1).unwrap();
+    assert!(!proc.all_finished());
+    assert!(proc.any_failed().is_none());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !proc.all_finished() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(proc.all_finished());
+    assert!(proc.any_failed().is_none());
+    assert!(proc.wait().is_ok());
+
+    // a single command, since the stages checked depend on pipefail
+    let mut proc = spawn!(exit-with 3).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !proc.all_finished() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let err = proc.any_failed().unwrap();
+    assert!(err.to_string().contains("status code: 3"));
+    // polling again reports the same failure, which is kept for `wait()`
+    assert!(proc
+        .any_failed()
+        .unwrap()
+        .to_string()
+        .contains("status code: 3"));
+    assert!(proc.wait().is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_stdout_fd() {
+    use std::os::unix::io::BorrowedFd;
+    use std::process::{Command, Stdio};
+    let mut proc = spawn_with_output!(echo hello | cat).unwrap();
+    let fd = proc.stdout_fd().unwrap();
+    let stdout = unsafe { BorrowedFd::borrow_raw(fd) }
+        .try_clone_to_owned()
+        .unwrap();
+    let output = Command::new("tr")
+        .args(["a-z", "A-Z"])
+        .stdin(Stdio::from(stdout))
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"HELLO\n");
+    // everything was read by `tr` already
+    assert_eq!(proc.wait_with_output().unwrap(), "");
+}
+
+#[test]
+fn test_wait_deadline() {
+    use std::time::{Duration, Instant};
+    let deadline = Instant::now() + Duration::from_secs(10);
+    assert!(spawn!(echo xx | cat)
+        .unwrap()
+        .wait_deadline(deadline)
+        .is_ok());
+    assert!(spawn!(false).unwrap().wait_deadline(deadline).is_err());
+
+    let now = Instant::now();
+    let deadline = now + Duration::from_millis(200);
+    let mut fast = spawn!(sleep 0.1).unwrap();
+    let mut slow = spawn!(sleep 10 | cat).unwrap();
+    assert!(fast.wait_deadline(deadline).is_ok());
+    let err = slow.wait_deadline(deadline).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(now.elapsed() < Duration::from_secs(5));
+
+    // the `sleep` grandchild keeps stderr open after `sh` is killed
+    let now = Instant::now();
+    let deadline = now + Duration::from_millis(300);
+    let err = spawn!(sh -c "sleep 5; true")
+        .unwrap()
+        .wait_deadline(deadline)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(now.elapsed() < Duration::from_secs(3));
+}
+
 #[test]
 fn test_proc_env() {
     let output = run_fun!(FOO=100 printenv | grep FOO).unwrap();
     assert_eq!(output, "FOO=100");
+
+    assert_eq!(run_fun!(FOO="a=b c" printenv FOO).unwrap(), "a=b c");
+    assert_eq!(run_fun!(FOO="x==y" printenv FOO).unwrap(), "x==y");
+    assert_eq!(
+        run_fun!(FOO="héllo wörld" printenv FOO).unwrap(),
+        "héllo wörld"
+    );
+    let value = "-c B=c";
+    assert_eq!(run_fun!(A=$value printenv A).unwrap(), "-c B=c");
 }
 
 #[test]
@@ -205,6 +1045,85 @@ fn test_export_cmd() {
     assert!(run_cmd!(my_cmd2).is_ok());
 }
 
+#[test]
+#[rustfmt::skip]
+fn test_wait_with_all_and_code() {
+    use std::io::Write;
+    let (res, code, stdout, _) = spawn_with_output!(echo xx).unwrap().wait_with_all_and_code();
+    assert!(res.is_ok());
+    assert_eq!((code, stdout.as_str()), (Some(0), "xx"));
+
+    let (res, code, _, stderr) = spawn_with_output!(sh -c "echo err >&2; exit 3")
+        .unwrap()
+        .wait_with_all_and_code();
+    assert!(res.is_err());
+    assert_eq!((code, stderr.as_str()), (Some(3), "err"));
+
+    fn proxy_cmd(env: &mut CmdEnv) -> CmdResult {
+        let args = env.get_args();
+        let (_, code, _, _) = spawn_with_output!(sh -c $[args]).unwrap().wait_with_all_and_code();
+        writeln!(env.stdout(), "proxied")?;
+        match code {
+            Some(0 | 1) => Ok(()),
+            Some(code) => run_cmd!(exit-with $code),
+            None => run_cmd!(exit-with 255),
+        }
+    }
+    use_custom_cmd!(proxy_cmd);
+    let code_of = |mut children: FunChildren| children.wait_with_all_and_code().1;
+    assert_eq!(code_of(spawn_with_output!(proxy_cmd "exit 1").unwrap()), Some(0));
+    assert_eq!(code_of(spawn_with_output!(proxy_cmd "exit 4").unwrap()), Some(4));
+    assert_eq!(code_of(spawn_with_output!(exit-with 2).unwrap()), Some(2));
+    assert_eq!(code_of(spawn_with_output!(echo x | sh -c "cat; exit 9").unwrap()), Some(9));
+}
+
+#[test]
+fn test_register_all_commands() {
+    use std::io::Write;
+    #[cmd_lib::command]
+    fn marked_cmd(env: &mut CmdEnv) -> CmdResult {
+        let args = env.get_args().join(" ");
+        writeln!(env.stdout(), "marked {args}")
+    }
+
+    mod more_cmds {
+        use cmd_lib::*;
+        use std::io::Write;
+        #[cmd_lib::command]
+        fn marked_cmd2(env: &mut CmdEnv) -> CmdResult {
+            writeln!(env.stdout(), "marked2")
+        }
+    }
+
+    register_all_commands();
+    assert_eq!(run_fun!(marked_cmd a b).unwrap(), "marked a b");
+    assert_eq!(run_fun!(marked_cmd2).unwrap(), "marked2");
+}
+
+#[test]
+fn test_custom_cmd_vars() {
+    use std::io::Write;
+    fn show_vars(env: &mut CmdEnv) -> CmdResult {
+        let mut vars: Vec<String> = env.vars().keys().cloned().collect();
+        vars.sort();
+        let path = env.env_iter().any(|(k, v)| k == "PATH" && !v.is_empty());
+        let home = env.env_iter().filter(|(k, _)| k == "HOME").count();
+        writeln!(env.stdout(), "{} {path} {home}", vars.join(","))
+    }
+    fn show_location(env: &mut CmdEnv) -> CmdResult {
+        let (file, line) = env.caller_location();
+        let location = format!("{file}:{line}");
+        writeln!(env.stdout(), "{location}")
+    }
+    use_custom_cmd!(show_vars, show_location);
+    let (output, line) = (run_fun!(show_location).unwrap(), line!());
+    assert_eq!(output, format!("{}:{line}", file!()));
+    assert_eq!(
+        run_fun!(B=1 A=2 HOME=/tmp show_vars).unwrap(),
+        "A,B,HOME true 1"
+    );
+}
+
 #[test]
 fn test_escape() {
     let xxx = 42;
@@ -232,6 +1151,19 @@ fn test_current_dir() {
 /// ```
 fn test_redirect_fail() {}
 
+#[test]
+fn test_in_dir() {
+    let dir = "/tmp";
+    assert_eq!(run_fun!(in / pwd).unwrap(), "/");
+    assert_eq!(run_fun!(cd /tmp; in / pwd; pwd).unwrap(), "/tmp");
+    assert_eq!(run_fun!(cd /; in tmp pwd).unwrap(), "/tmp");
+    assert_eq!(run_fun!(echo xx | in $dir cat).unwrap(), "xx");
+    assert_eq!(run_fun!(in "/" ls | grep tmp).unwrap(), "tmp");
+    let err = run_cmd!(in /bad_dir ls).unwrap_err();
+    assert!(!err.to_string().contains("command not found"));
+    assert_eq!(run_fun!(echo in).unwrap(), "in");
+}
+
 #[test]
 fn test_buitin_stdout_redirect() {
     let f = "/tmp/builtin";
@@ -241,6 +1173,351 @@ fn test_buitin_stdout_redirect() {
     run_cmd!(rm -f $f).unwrap();
 }
 
+#[test]
+fn test_builtin_mkdir() {
+    let dir = "/tmp/cmd_lib_builtin_mkdir";
+    run_cmd!(rm -rf $dir).unwrap();
+    assert!(run_cmd!(mkdir $dir/a/b).is_err());
+    assert!(run_cmd!(mkdir -p $dir/a/b).is_ok());
+    assert!(run_cmd!(mkdir -p $dir/a/b).is_ok());
+    assert!(run_cmd!(mkdir $dir/a).is_err());
+    assert!(run_cmd!(cd $dir; mkdir c).is_ok());
+    assert!(std::path::Path::new(&format!("{dir}/c")).is_dir());
+
+    // other options run the mkdir program
+    assert!(run_cmd!(mkdir -m 700 $dir/d).is_ok());
+    assert_eq!(run_fun!(stat -c %a $dir/d).unwrap(), "700");
+    assert!(run_cmd!(mkdir -pv $dir/e/f; mkdir -p -- $dir/g).is_ok());
+    assert!(std::path::Path::new(&format!("{dir}/e/f")).is_dir());
+    assert!(std::path::Path::new(&format!("{dir}/g")).is_dir());
+    run_cmd!(rm -rf $dir).unwrap();
+}
+
+#[test]
+fn test_builtin_stdout_append() {
+    let f = "/tmp/builtin_append";
+    run_cmd!(rm -f $f).unwrap();
+    run_cmd!(echo a >> $f; echo b >> $f).unwrap();
+    assert_eq!(run_fun!(cat $f).unwrap(), "a\nb");
+    run_cmd!(echo c > $f; echo d 1>> $f; echo -n e &>> $f).unwrap();
+    assert_eq!(run_fun!(cat $f).unwrap(), "c\nd\ne");
+    run_cmd!(rm -f $f).unwrap();
+}
+
+#[test]
+fn test_builtin_tee() {
+    let f1 = "/tmp/builtin_tee1";
+    let f2 = "/tmp/builtin_tee2";
+    assert_eq!(run_fun!(echo xx | tee $f1 $f2).unwrap(), "xx");
+    assert_eq!(run_fun!(echo yy | tee -a $f1 | wc -l).unwrap(), "1");
+    assert_eq!(run_fun!(cat $f1).unwrap(), "xx\nyy");
+    assert_eq!(run_fun!(cat $f2).unwrap(), "xx");
+    assert!(run_cmd!(echo zz | tee /bad_dir/f).is_err());
+
+    // other options run the tee program
+    assert_eq!(run_fun!(echo zz | tee -i $f1).unwrap(), "zz");
+    assert_eq!(run_fun!(echo zz | tee -p -- $f2).unwrap(), "zz");
+    assert_eq!(run_fun!(cat $f1 $f2).unwrap(), "zz\nzz");
+    run_cmd!(rm -f $f1 $f2).unwrap();
+}
+
+#[test]
+fn test_builtin_cmp() {
+    let f1 = "/tmp/builtin_cmp1";
+    let f2 = "/tmp/builtin_cmp2";
+    run_cmd!(echo abcd > $f1; echo abcd > $f2).unwrap();
+    assert!(run_cmd!(cmp $f1 $f2).is_ok());
+
+    run_cmd!(echo abXd > $f2).unwrap();
+    let (res, _, stderr) = spawn_with_output!(cmp $f1 $f2).unwrap().wait_with_all();
+    assert!(res.is_err());
+    assert_eq!(
+        stderr,
+        "cmp: /tmp/builtin_cmp1 /tmp/builtin_cmp2 differ: byte 3"
+    );
+
+    run_cmd!(echo -n abc > $f2).unwrap();
+    let (res, _, stderr) = spawn_with_output!(cmp $f1 $f2).unwrap().wait_with_all();
+    assert!(res.is_err());
+    assert_eq!(stderr, "cmp: EOF on /tmp/builtin_cmp2 after byte 3");
+
+    let (res, _, stderr) = spawn_with_output!(cmp -s $f1 $f2).unwrap().wait_with_all();
+    assert!(res.is_err());
+    assert_eq!(stderr, "");
+    assert!(run_cmd!(cmp -s $f1 $f1).is_ok());
+
+    // other options run the cmp program
+    assert!(run_cmd!(cmp -l $f1 $f2).is_err());
+    assert!(run_cmd!(cmp -- $f1 $f1).is_ok());
+
+    assert!(run_cmd!(cmp $f1 /tmp/builtin_cmp_missing).is_err());
+    assert!(run_cmd!(cmp $f1).is_err());
+    run_cmd!(rm -f $f1 $f2).unwrap();
+}
+
+#[test]
+#[rustfmt::skip]
+fn test_builtin_exit_with() {
+    assert!(run_cmd!(exit-with 0).is_ok());
+    let err = run_cmd!(exit-with 7).unwrap_err();
+    assert!(err.to_string().contains("status code: 7"));
+    assert!(run_cmd!(exit-with 7 | cat).is_err());
+    assert!(run_cmd!(exit-with).is_err());
+    assert!(run_cmd!(exit-with x).is_err());
+    assert!(run_cmd!(ignore exit-with 1).is_ok());
+}
+
+#[test]
+fn test_builtin_log_format() {
+    let n = 3;
+    assert!(run_cmd!(info "count: %03d" $n).is_ok());
+    assert!(run_cmd!(warn "100%" done).is_ok());
+    let err = run_cmd!(info "count: %d" not_a_number).unwrap_err();
+    assert!(err.to_string().contains("info: invalid number"));
+}
+
+#[test]
+fn test_builtin_label() {
+    assert_eq!(run_fun!(label "step 1"; echo xx).unwrap(), "xx");
+    assert_eq!(run_fun!(label step 2 | wc -c).unwrap(), "0");
+    assert!(run_cmd!(label; ls /bad_dir_for_label 2>/dev/null).is_err());
+    assert!(run_cmd!(label "step 3" -- echo xx; label "step 4" -- true).is_ok());
+    assert_eq!(
+        run_fun!(label "a" -- echo xx | label "b" -- wc -c).unwrap(),
+        "3"
+    );
+    assert!(run_cmd!(label "c" -- ls /bad_dir_for_label).is_err());
+    let name = "d";
+    assert_eq!(
+        run_fun!(label "$name" -- echo xx > /dev/null; label "$name").unwrap(),
+        ""
+    );
+}
+
+#[test]
+fn test_raw_args() {
+    let fmt = "%s-%s";
+    assert_eq!(run_fun!(raw printf $fmt a b).unwrap(), "a-b");
+    assert_eq!(run_fun!(ignore raw printf $fmt a b).unwrap(), "a-b");
+    assert!(run_cmd!(raw false).is_err());
+}
+
+#[test]
+fn test_quiet_stderr() {
+    let (res, stdout, stderr) = spawn_with_output!(quiet_stderr sh -c "echo out; echo err >&2")
+        .unwrap()
+        .wait_with_all();
+    assert!(res.is_ok());
+    assert_eq!(stdout, "out");
+    assert_eq!(stderr, "");
+    assert!(run_cmd!(quiet_stderr ls /bad_dir_for_quiet).is_err());
+    assert_eq!(
+        run_fun!(quiet_stderr sh -c "echo err >&2" 2>&1).unwrap(),
+        "err"
+    );
+}
+
+#[test]
+fn test_quiet_and_show_stdout() {
+    assert_eq!(
+        run_fun!(quiet_stdout echo hidden; echo shown).unwrap(),
+        "shown"
+    );
+    assert_eq!(
+        run_fun!(show_stdout echo printed; echo captured).unwrap(),
+        "captured"
+    );
+    assert_eq!(run_fun!(echo a | quiet_stdout cat).unwrap(), "");
+    assert!(run_fun!(quiet_stdout ls; ls /bad_dir).is_err());
+    assert_eq!(run_fun!(echo quiet_stdout).unwrap(), "quiet_stdout");
+}
+
+#[test]
+fn test_show_stderr() {
+    let (res, stdout, stderr) = spawn_with_output!(sh -c "echo err >&2; echo out")
+        .unwrap()
+        .wait_with_all();
+    assert!(res.is_ok());
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("out", "err"));
+
+    let (res, stdout, stderr) = spawn_with_output!(show_stderr sh -c "echo err >&2; echo out")
+        .unwrap()
+        .wait_with_all();
+    assert!(res.is_ok());
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("out", ""));
+
+    let (_, stdout, stderr) = spawn_with_output!(sh -c "echo err >&2; echo out" 2>&2)
+        .unwrap()
+        .wait_with_all();
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("out", ""));
+    assert_eq!(run_fun!(echo show_stderr).unwrap(), "show_stderr");
+}
+
+#[test]
+fn test_swap_out_err() {
+    let (res, stdout, stderr) = spawn_with_output!(swap_out_err sh -c "echo err >&2; echo out")
+        .unwrap()
+        .wait_with_all();
+    assert!(res.is_ok());
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("err", "out"));
+
+    assert_eq!(
+        run_fun!(swap_out_err sh -c "echo err >&2; echo out" | tr a-z A-Z).unwrap(),
+        "ERR"
+    );
+    assert_eq!(
+        run_fun!(swap_out_err sh -c "echo err >&2; echo out" 2>/dev/null).unwrap(),
+        ""
+    );
+    assert_eq!(run_fun!(swap_out_err echo hi).unwrap(), "");
+}
+
+#[test]
+fn test_command_substitution() {
+    assert_eq!(run_fun!(echo $(echo hello)).unwrap(), "hello");
+    assert_eq!(
+        run_fun!(echo x-$(echo a b | tr a-z A-Z)-y).unwrap(),
+        "x-A B-y"
+    );
+    assert_eq!(
+        run_fun!(printf "%s|" $(printf "a\nb\n\n")).unwrap(),
+        "a\nb|"
+    );
+    assert_eq!(run_fun!(echo $(echo $(echo nested))).unwrap(), "nested");
+    let name = "world";
+    assert_eq!(run_fun!(echo $(echo hello $name)).unwrap(), "hello world");
+
+    assert_eq!(
+        run_fun!(printf "%s|" $[(printf " a b\n c  \n")]).unwrap(),
+        "a|b|c|"
+    );
+    assert_eq!(run_fun!(printf "%s|" $(echo a b)).unwrap(), "a b|");
+    assert_eq!(run_fun!(printf "[%s]" $[(true)]).unwrap(), "[]");
+
+    assert!(run_cmd!(echo $(false)).is_err());
+    assert!(run_fun!(echo $(ls /bad_dir_for_substitution)).is_err());
+    assert!(spawn!(echo $(false)).is_err());
+    assert!(run_cmd!(echo $[(false)]).is_err());
+
+    // each substitution runs right before its own statement
+    assert_eq!(run_fun!(cd /tmp; echo $(pwd)).unwrap(), "/tmp");
+    assert_eq!(
+        run_fun!(export SUB_VAR=exported; echo $(printenv SUB_VAR)).unwrap(),
+        "exported"
+    );
+    let file = "/tmp/cmd_lib_test_substitution_when";
+    let no = false;
+    run_cmd!(rm -f $file; when $no echo $(touch $file)).unwrap();
+    assert!(!std::path::Path::new(file).exists());
+    assert!(run_cmd!(when $no echo $(false)).is_ok());
+}
+
+#[test]
+fn test_when_condition() {
+    let yes = true;
+    let no = false;
+    assert_eq!(run_fun!(when $yes echo a).unwrap(), "a");
+    assert_eq!(run_fun!(when $no echo a).unwrap(), "");
+    assert_eq!(
+        run_fun!(echo a; when $no false; when ${yes} echo b | tr b c).unwrap(),
+        "c"
+    );
+    assert!(run_cmd!(when $no false).is_ok());
+    assert!(run_cmd!(when $yes false).is_err());
+    assert!(spawn!(when $no false).unwrap().wait().is_ok());
+    assert_eq!(
+        run_fun!(when $no cd /bad_dir_for_when; pwd).unwrap(),
+        run_fun!(pwd).unwrap()
+    );
+    assert_eq!(run_fun!(echo when).unwrap(), "when");
+}
+
+#[test]
+fn test_raw_string_interpolation() {
+    let name = "rust";
+    assert_eq!(run_fun!(echo r"\d+ $name").unwrap(), r"\d+ $name");
+    assert_eq!(run_fun!(echo $r"\d+ $name").unwrap(), r"\d+ rust");
+    assert_eq!(
+        run_fun!(echo $r#"a "${name}" $$b\n"#).unwrap(),
+        r#"a "rust" $b\n"#
+    );
+    assert_eq!(
+        run_fun!(echo "x y" | awk $r#"/x/ {print "$name:" $$NF}"#).unwrap(),
+        "rust:y"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_umask() {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let dir = "/tmp/cmd_lib_umask_test";
+    run_cmd!(rm -rf $dir; mkdir -p $dir).unwrap();
+    run_cmd!(umask 077 touch $dir/a).unwrap();
+    assert_eq!(mode("/tmp/cmd_lib_umask_test/a"), 0o600);
+    run_cmd!(umask 027 echo hi > $dir/b).unwrap();
+    assert_eq!(mode("/tmp/cmd_lib_umask_test/b"), 0o640);
+    assert_eq!(run_fun!(umask 027 sh -c "umask").unwrap(), "0027");
+    run_cmd!(rm -rf $dir).unwrap();
+}
+
+#[test]
+fn test_brace_expansion() {
+    assert_eq!(run_fun!(echo file.{txt,md}).unwrap(), "file.txt file.md");
+    assert_eq!(run_fun!(echo {a,b}-{1,2}).unwrap(), "a-1 a-2 b-1 b-2");
+    assert_eq!(run_fun!(echo f{,.bak}).unwrap(), "f f.bak");
+    assert_eq!(run_fun!(echo "{a,b}" {} {x}).unwrap(), "{a,b} {} {x}");
+    assert_eq!(run_fun!(echo {a,{b,c}}).unwrap(), "a {b,c}");
+    assert_eq!(run_fun!(echo {a,b}; echo c).unwrap(), "c");
+}
+
+#[test]
+#[cfg(feature = "pty")]
+fn test_pty() {
+    assert_eq!(run_cmd_pty!(test -t 1).unwrap(), "");
+    assert!(run_cmd!(test -t 1 > /dev/null).is_err());
+    assert_eq!(
+        run_cmd_pty!(sh -c "echo out; echo err >&2").unwrap(),
+        "out\nerr"
+    );
+    assert!(run_cmd_pty!(false).is_err());
+    assert!(run_cmd_pty!(echo xx | cat).is_err());
+
+    let mut proc = spawn_pty!(tty).unwrap();
+    assert!(proc.wait_with_output().unwrap().starts_with("/dev/"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_bytes_as_var() {
+    let bytes: &[u8] = b"caf\xe9";
+    let mut buf = vec![];
+    spawn_with_output!(printf "%s|" $bytes "${bytes}x")
+        .unwrap()
+        .wait_with_raw_output(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"caf\xe9|caf\xe9x|");
+
+    let vec_bytes = b"a b".to_vec();
+    assert_eq!(run_fun!(echo $vec_bytes).unwrap(), "a b");
+    let array_bytes = *b"xyz";
+    assert_eq!(run_fun!(echo $array_bytes).unwrap(), "xyz");
+}
+
+#[test]
+fn test_duration_as_var() {
+    use std::time::Duration;
+    let d = Duration::from_millis(1500);
+    assert_eq!(run_fun!(echo $d "${d}s").unwrap(), "1.5 1.5s");
+    let d = Duration::from_secs(2);
+    assert_eq!(run_fun!(echo $d).unwrap(), "2");
+    let d = &Duration::from_micros(10);
+    assert_eq!(run_fun!(echo $d).unwrap(), "0.00001");
+    let d = Duration::from_millis(10);
+    assert!(run_cmd!(sleep $d).is_ok());
+}
+
 #[test]
 fn test_path_as_var() {
     let dir = std::path::Path::new("/");
@@ -250,6 +1527,49 @@ fn test_path_as_var() {
     assert_eq!("/", run_fun!(cd $dir2; pwd).unwrap());
 }
 
+#[test]
+fn test_dash_args() {
+    // only the builtin echo interprets `-n`
+    assert_eq!(run_fun!(echo -n xx | wc -c).unwrap(), "2");
+    assert_eq!(run_fun!(/bin/echo -n xx | wc -c).unwrap(), "2");
+    assert_eq!(
+        run_fun!(printf "%s|" -- -n --color=auto -1 - --).unwrap(),
+        "--|-n|--color=auto|-1|-|--|"
+    );
+    let pattern = "-v";
+    assert_eq!(run_fun!(printf "a\n-v\n" | grep -- $pattern).unwrap(), "-v");
+    let opts = ["-n", "--", "-e"];
+    assert_eq!(run_fun!(printf "%s|" $[opts]).unwrap(), "-n|--|-e|");
+}
+
+#[test]
+fn test_vec_var_join() {
+    let features = vec!["a".to_string(), "b c".to_string()];
+    assert_eq!(run_fun!(printf "[%s]" $[features; ","]).unwrap(), "[a,b c]");
+    assert_eq!(
+        run_fun!(printf "[%s]" --features=$[features; ", "]).unwrap(),
+        "[--features=a, b c]"
+    );
+    let empty: Vec<String> = vec![];
+    assert_eq!(run_fun!(printf "[%s]" $[empty; ","]).unwrap(), "[]");
+    assert_eq!(features.len(), 2);
+}
+
+#[test]
+fn test_env_map() {
+    use std::collections::{BTreeMap, HashMap};
+    let env = HashMap::from([("FOO", "1"), ("BAR", "x y")]);
+    assert_eq!(
+        run_fun!($[env] sh -c r#"echo "$FOO,$BAR""#).unwrap(),
+        "1,x y"
+    );
+    let map: BTreeMap<String, String> = [("FOO".into(), "2".into())].into();
+    let env = &map;
+    assert_eq!(run_fun!($[env] FOO=3 sh -c "echo $$FOO").unwrap(), "3");
+    assert_eq!(run_fun!(FOO=3 $[env] sh -c "echo $$FOO").unwrap(), "2");
+    assert_eq!(run_fun!(env -i $[env]).unwrap(), "FOO=2");
+}
+
 #[test]
 fn test_empty_arg() {
     let opt = "";
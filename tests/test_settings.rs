@@ -0,0 +1,83 @@
+// Tests changing the process wide settings, kept apart from the tests depending on the default
+// settings, and run one at a time.
+use cmd_lib::*;
+use std::ffi::OsString;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+static SETTINGS_LOCK: Mutex<()> = Mutex::new(());
+
+// holds the settings lock, and restores the setting kept in environment variable `name` when
+// dropped, even if an assert failed
+struct Setting {
+    name: &'static str,
+    prev: Option<OsString>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Setting {
+    fn save(name: &'static str) -> Self {
+        let lock = SETTINGS_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        Self {
+            name,
+            prev: std::env::var_os(name),
+            _lock: lock,
+        }
+    }
+}
+
+impl Drop for Setting {
+    fn drop(&mut self) {
+        match self.prev.take() {
+            Some(prev) => std::env::set_var(self.name, prev),
+            None => std::env::remove_var(self.name),
+        }
+    }
+}
+
+#[test]
+fn test_error_stderr_lines() {
+    let _setting = Setting::save("CMD_LIB_ERROR_STDERR_LINES");
+    set_error_stderr_lines(2);
+    let err = run_cmd!(bash -c "echo e1 >&2; echo e2 >&2; echo e3 >&2; exit 3").unwrap_err();
+    assert!(err.to_string().ends_with("; last stderr lines:\ne2\ne3"));
+    let err = run_fun!(bash -c "echo oops >&2; exit 3").unwrap_err();
+    assert!(err.to_string().ends_with("; last stderr lines:\noops"));
+    set_error_stderr_lines(0);
+    let err = run_cmd!(bash -c "echo oops >&2; exit 3").unwrap_err();
+    assert!(!err.to_string().contains("last stderr lines"));
+}
+
+#[test]
+fn test_stderr_capture_limit() {
+    let _setting = Setting::save("CMD_LIB_STDERR_CAPTURE_LIMIT");
+    set_stderr_capture_limit(Some(10));
+    let (res, _, stderr) =
+        spawn_with_output!(bash -c r"for i in $(seq 1 1000); do echo error $i >&2; done")
+            .unwrap()
+            .wait_with_all();
+    assert!(res.is_ok());
+    assert_eq!(stderr, "error 1\n...(stderr truncated)");
+
+    // a long line without newline is cut while reading, not after
+    let (res, _, stderr) = spawn_with_output!(sh -c "head -c 50000000 /dev/zero | tr '\\0' x >&2")
+        .unwrap()
+        .wait_with_all();
+    assert!(res.is_ok());
+    assert_eq!(stderr, "...(stderr truncated)");
+}
+
+#[test]
+fn test_pipe_buffer_size() {
+    use std::io::{BufRead, BufReader};
+    let _setting = Setting::save("CMD_LIB_PIPE_BUFFER_SIZE");
+    set_pipe_buffer_size(16);
+    let mut lines = vec![];
+    let res = spawn_with_output!(seq 1 1000)
+        .unwrap()
+        .wait_with_pipe(&mut |pipe| {
+            lines = BufReader::new(pipe).lines().map_while(Result::ok).collect();
+        });
+    assert!(res.is_ok());
+    assert_eq!(lines.len(), 1000);
+    assert_eq!(lines[999], "1000");
+}